@@ -1,12 +1,25 @@
 #[macro_use]
 extern crate clap;
 use async_jsonrpc_client::HttpTransport;
+use clap::{Arg, ArgMatches};
 use cli_table::{format::Justify, print_stdout, Cell, Style, Table};
 use ethabi::{Address, Uint};
+use std::collections::HashMap;
 use std::error::Error;
 use vault::{
-    get_simulation, CdpManager, HttpBlockchainReader, Median, Pair, Saver, Scenario, Spot,
+    backtest, get_close_plan, get_default_vault, get_execution_reports, get_leverage_plan,
+    get_liquidation_reports, get_permissions, get_simulation, get_start_vault, read_ticks,
+    Calibration, CdpManager, CloseRule, CloseTo, CloseTrigger, Dog, Exposure, Factory, Friction,
+    HttpBlockchainReader, Leverage, LiquidationTerms, Median, MonteCarlo, Pair, ProxyRegistry,
+    Saver, Scenario, Spot, SubscriberStats, Trace, TradeCost, Unwind, Vault, Wad,
+    MCD_DEPLOYMENT_BLOCK, SIMULATION_START_PRICE,
 };
+use web3::types::BlockNumber;
+
+const DAI_ADDRESS: &str = "6b175474e89094c44da98b954eedeac495271d0f";
+/// The OSM makes a price current an hour after it was pushed.
+const OSM_DELAY: u64 = 3600;
+const DAI_ETH_PAIR_ADDRESS: &str = "a478c2975ab1ea89e8196811f51a7b7ade33eb11";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -21,19 +34,138 @@ async fn main() -> Result<(), Box<dyn Error>> {
          (@arg VAULT_ID: +required "The ID of the vault to check" )
          (about: "display the vault state for the choosen price")
          (@arg next: --next "if present, computations are based on the next price" )
-         (@arg price: -p --price +takes_value  "if present, computations are based on this price (DAI / ETH)" )
+         (@arg price: -p --price +takes_value  "if present, computations are based on this price (DAI / collateral)" )
          (@arg max_ratio: --max_ratio +takes_value  "set this to override the max_ratio of the current vault (in %)" )
          (@arg boost_ratio: --boost_ratio +takes_value  "set this to override the boost_ratio of the current vault (in %)" )
          (@arg min_ratio: --min_ratio +takes_value  "set this to override the min_ratio of the current vault (in %)" )
          (@arg repay_ratio: --repay_ratio +takes_value  "set this to override the repay_ratio of the current vault (in %)" )
+         (@arg pool: --pool conflicts_with[boost_gas boost_slippage repay_gas repay_slippage] "if present, the friction is the slippage of the uniswap DAI pool of the collateral instead of a constant" )
+         (@arg trace: --trace "if present, print every boost and repay the automation takes to reach the price" )
+         (args: &get_close_rule_args())
+         (args: &get_friction_args())
+        )
+        (@subcommand whatif =>
+         (about: "display the vault state after manual deposits, withdrawals, draws and paybacks")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg VAULT_ID: +required "The ID of the vault to check" )
+         (@arg price: -p --price +takes_value  "if present, computations are based on this price (DAI / collateral)" )
+         (@arg deposit: --deposit +takes_value "the collateral to deposit" )
+         (@arg withdraw: --withdraw +takes_value "the collateral to withdraw" )
+         (@arg draw: --draw +takes_value "the DAI to draw" )
+         (@arg payback: --payback +takes_value "the DAI to pay back" )
+        )
+        (@subcommand close =>
+         (about: "plan repaying the debt of the vault by selling its collateral on uniswap")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg VAULT_ID: +required "The ID of the vault to close" )
+         (@arg dry_run: --("dry-run") "print the plan without sending any transaction" )
+         (@arg price: -p --price +takes_value  "if present, the Vat checks use this oracle price (DAI / collateral)" )
+         (@arg dai: --dai +takes_value "if present, only this much of the debt is repaid (in DAI)" )
+         (@arg flash_fee: --flash_fee +takes_value default_value("0") "The fee of the flash loan (in %)" )
+        )
+        (@subcommand leverage =>
+         (about: "plan boosting the vault to a target ratio by buying collateral on uniswap")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg VAULT_ID: +required "The ID of the vault to boost" )
+         (@arg RATIO: +required "The target ratio (in %)" )
+         (@arg price: -p --price +takes_value  "if present, the Vat checks use this oracle price (DAI / collateral)" )
+         (@arg flash_fee: --flash_fee +takes_value default_value("0") "The fee of the flash loan (in %)" )
+        )
+        (@subcommand exposure =>
+         (about: "display the vault together with the owner wallets")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg VAULT_ID: +required "The ID of the vault to check" )
+         (@arg next: --next "if present, computations are based on the next price" )
+         (@arg price: -p --price +takes_value  "if present, computations are based on this price (DAI / collateral)" )
+        )
+        (@subcommand list =>
+         (about: "list the vaults of a wallet or of its proxy")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg ADDRESS: +required "The wallet (or proxy) owning the vaults" )
+        )
+        (@subcommand permissions =>
+         (about: "list every address that can operate the vault")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg VAULT_ID: +required "The ID of the vault to check" )
+        )
+        (@subcommand subscribers =>
+         (about: "display statistics about every vault subscribed to the automation")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg per_page: --per_page +takes_value default_value("100") "The number of subscribers read per call" )
+         (@arg token: -t --token +takes_value "if present, only the vaults with this collateral (e.g. WETH) are considered" )
+         (@arg price: -p --price +takes_value requires("token") "if present, count the vaults triggered at this price (DAI / collateral)" )
+        )
+        (@subcommand history_settings =>
+         (name: "history-settings")
+         (about: "display the automation settings changes of the vault (min, repay, boost and max ratios)")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg VAULT_ID: +required "The ID of the vault to check" )
+        )
+        (@subcommand history_actions =>
+         (name: "history-actions")
+         (about: "display the boosts and repays executed on the vault and compare them with the predictions (needs an archive node)")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg VAULT_ID: +required "The ID of the vault to check" )
+        )
+        (@subcommand history_liquidations =>
+         (name: "history-liquidations")
+         (about: "display the liquidations of the vault since April 2021, with the collateral sold and returned next to the estimates (needs an archive node)")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg VAULT_ID: +required "The ID of the vault to check" )
+        )
+        (@subcommand calibrate =>
+         (about: "fit the boost and repay friction on past executions (needs an archive node)")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg VAULT_ID: "The ID of the vault whose executions are used (default to every vault)" )
+        )
+        (@subcommand montecarlo =>
+         (about: "run the vault along random price paths (geometric brownian motion)")
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call" )
+         (@arg VAULT_ID: +required "The ID of the vault to simulate" )
+         (@arg drift: --drift +takes_value default_value("0") "The annual drift of the price (0.1 = +10% a year)" )
+         (@arg volatility: --volatility +takes_value default_value("0.8") "The annual volatility of the price" )
+         (@arg days: --days +takes_value default_value("365") "The horizon of the simulation, in days" )
+         (@arg steps: --steps +takes_value default_value("365") "The number of prices drawn along each path" )
+         (@arg paths: --paths +takes_value default_value("1000") "The number of paths" )
+         (@arg seed: --seed +takes_value default_value("1") "The seed of the random generator, the same seed gives the same result" )
+         (@arg delayed: --delayed "if present, the automation and the liquidations act on the OSM price, an hour late" )
+         (args: &get_close_rule_args())
+         (@arg friction: -f --friction +takes_value  default_value("0.03") "The friction takes in account the transactions fees, and market friction." )
+         (@arg max_ratio: --max_ratio +takes_value  "set this to override the max_ratio of the current vault (in %)" )
+         (@arg boost_ratio: --boost_ratio +takes_value  "set this to override the boost_ratio of the current vault (in %)" )
+         (@arg min_ratio: --min_ratio +takes_value  "set this to override the min_ratio of the current vault (in %)" )
+         (@arg repay_ratio: --repay_ratio +takes_value  "set this to override the repay_ratio of the current vault (in %)" )
+         (args: &get_friction_args())
+        )
+        (@subcommand backtest =>
+         (about: "replay a vault over historical prices and compare it with holding the collateral")
+         (@arg csv: --csv +takes_value required_unless("osm") conflicts_with("osm") "A CSV file of historical prices: a unix timestamp or a YYYY-MM-DD date, then the price" )
+         (@arg osm: --osm +takes_value "if present, the prices are read from the oracle of this ilk (e.g. ETH-A)" )
+         (@arg NODE: -n --node +takes_value default_value("localhost:8545") "Ethereum node to call, with --osm" )
+         (@arg from_block: --from_block +takes_value requires("osm") "The first block read with --osm (default to the MCD deployment)" )
+         (@arg to_block: --to_block +takes_value requires("osm") "The last block read with --osm (default to the latest)" )
+         (@arg capital: --capital +takes_value default_value("10000") "The equity the vault starts with (in DAI)" )
+         (@arg start_ratio: --start_ratio +takes_value "The ratio the vault starts at (in %, default to the boost ratio)" )
+         (@arg min_ratio: --min_ratio +takes_value default_value("160") "The min ratio (in %)" )
+         (@arg repay_ratio: --repay_ratio +takes_value default_value("180") "The repay ratio (in %)" )
+         (@arg boost_ratio: --boost_ratio +takes_value default_value("180") "The boost ratio (in %)" )
+         (@arg max_ratio: --max_ratio +takes_value default_value("220") "The max ratio (in %)" )
+         (@arg liquidation_ratio: --liquidation_ratio +takes_value default_value("150") conflicts_with("osm") "The liquidation ratio (in %), read from chain with --osm" )
+         (@arg delayed: --delayed "if present, the automation and the liquidations act on the OSM price, an hour late" )
+         (args: &get_close_rule_args())
+         (@arg penalty: --penalty +takes_value default_value("13") conflicts_with("osm") "The liquidation penalty (in %), with --osm the penalty and the auctions are read from chain" )
+         (@arg friction: -f --friction +takes_value  default_value("0.03") "The friction takes in account the transactions fees, and market friction." )
+         (args: &get_friction_args())
         )
         (@subcommand optimize =>
-         (about: "launch a serie of simulations to choose the best ratios")
+         (about: "launch a serie of simulations to choose the best ratios, the price starts at 100")
+         (args: &get_close_rule_args())
          (@arg friction: -f --friction +takes_value  default_value("0.03") "The friction takes in account the transactions fees, and market friction." )
          (@arg increase: --increase +takes_value  default_value("10") "The price increase to simulate." )
          (@arg start:  --start +takes_value  default_value("180") "The ratio at the start of the simulation (in %)." )
          (@arg end:  --end +takes_value  default_value("250") "The ratio at the end of the simulation (in %)." )
          (@arg list: --list "if present, show the result as a list (default to table)" )
+         (args: &get_friction_args())
         )
         )
         .get_matches();
@@ -45,56 +177,648 @@ async fn main() -> Result<(), Box<dyn Error>> {
         let transport = HttpTransport::new(node);
         let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
         let saver = Saver::new(&reader)?;
-        let cdp_manager: CdpManager = CdpManager::new(&reader)?;
         let vault_id = Uint::from(vault_id);
-        let price;
-        let wbtc_eth_pair_address: Address = "Bb2b8038a1640196FbE3e38816F3e67Cba72D940".parse()?;
-        let wbtc_eth_pair = Pair::new(&reader, wbtc_eth_pair_address)?;
-        let wbtc_price = wbtc_eth_pair.get_price_0().await?;
-
-        if matches.is_present("price") {
-            let price_f64 = value_t_or_exit!(matches.value_of("price"), f64);
-            price = Uint::from((price_f64 * 1000.0) as i64) * Uint::exp10(15);
-        } else {
-            let spot: Spot = Spot::new(&reader)?;
-            let next = matches.is_present("next");
-            let ilk_id = cdp_manager.get_ilk_id(vault_id).await?;
-            let median_address = spot.get_median_address(&ilk_id).await?;
-            let median = Median::new(&reader, median_address)?;
-            price = median.get_price(next).await?
+
+        let price = get_price(&reader, vault_id, matches).await?;
+        let mut vault = saver.get_vault(vault_id).await?;
+        let cdp_manager = CdpManager::new(&reader)?;
+        let ilk_id = cdp_manager.get_ilk_id(vault_id).await?;
+        if override_ratios(&mut vault, matches) {
+            let min_limit = saver.get_min_limit(&ilk_id).await?;
+            let ilk_info = saver.get_ilk_info(&ilk_id, vault_id).await?;
+            vault
+                .check_ratios(min_limit, ilk_info.mat.to_wad())
+                .map_err(|e| format!("invalid ratios:\n{}", e))?;
+        }
+        let current_price = get_ilk_price(&reader, &ilk_id, false).await?;
+        vault.close_rules = get_close_rules(matches, current_price)?;
+        let friction = match matches.is_present("pool") {
+            true => {
+                let factory = Factory::new(&reader)?;
+                let pool = factory
+                    .get_pool(&DAI_ADDRESS.parse()?, &vault.token.address)
+                    .await?;
+                Friction::Pool(pool)
+            }
+            false => get_friction(matches, friction)?,
+        };
+        vault = match matches.is_present("trace") {
+            true => {
+                let trace = vault.trace_vault(price, &friction)?;
+                print_trace(&trace);
+                trace.vault
+            }
+            false => vault.predict_vault(price, &friction)?,
+        };
+        let proxy_registry = ProxyRegistry::new(&reader)?;
+        match proxy_registry.get_owner(&vault.owner).await? {
+            Some(proxy_owner) => {
+                println!("{:<11}: {:#x}", "proxy", vault.owner);
+                println!("{:<11}: {:#x}", "owner", proxy_owner);
+            }
+            None => println!("{:<11}: {:#x}", "owner", vault.owner),
         }
+        let terms = Dog::new(&reader)?.get_liquidation_terms(&ilk_id).await?;
+        vault.show(price, get_btc_price(&reader).await?, &terms)?;
+    } else if let Some(matches) = matches.subcommand_matches("whatif") {
+        let vault_id = value_t_or_exit!(matches.value_of("VAULT_ID"), u128);
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        let saver = Saver::new(&reader)?;
+        let vault_id = Uint::from(vault_id);
+        let price = get_price(&reader, vault_id, matches).await?;
         let mut vault = saver.get_vault(vault_id).await?;
-        if matches.is_present("max_ratio") {
-            let max_ratio_pc = value_t_or_exit!(matches.value_of("max_ratio"), usize);
-            let max_ratio = Uint::from(max_ratio_pc) * Uint::exp10(16);
-            vault.max_ratio = max_ratio;
-        }
-        if matches.is_present("boost_ratio") {
-            let boost_ratio_pc = value_t_or_exit!(matches.value_of("boost_ratio"), usize);
-            let boost_ratio = Uint::from(boost_ratio_pc) * Uint::exp10(16);
-            vault.boost_ratio = boost_ratio;
-        }
-        if matches.is_present("min_ratio") {
-            let min_ratio_pc = value_t_or_exit!(matches.value_of("min_ratio"), usize);
-            let min_ratio = Uint::from(min_ratio_pc) * Uint::exp10(16);
-            vault.min_ratio = min_ratio;
-        }
-        if matches.is_present("repay_ratio") {
-            let repay_ratio_pc = value_t_or_exit!(matches.value_of("repay_ratio"), usize);
-            let repay_ratio = Uint::from(repay_ratio_pc) * Uint::exp10(16);
-            vault.repay_ratio = repay_ratio;
-        }
-        vault = vault.predict_vault(price, friction)?;
-        vault.show(price, wbtc_price)?;
+        let cdp_manager = CdpManager::new(&reader)?;
+        let ilk_id = cdp_manager.get_ilk_id(vault_id).await?;
+        let limits = saver
+            .get_ilk_info(&ilk_id, vault_id)
+            .await?
+            .get_vat_limits()?;
+        let amount = |arg: &str| match matches.is_present(arg) {
+            true => Wad::from_f64(value_t_or_exit!(matches.value_of(arg), f64)).map(Some),
+            false => Ok(None),
+        };
+        let symbol = vault.token.symbol.to_lowercase();
+        // the changes that make the vault safer go first, as a single transaction would
+        if let Some(deposit) = amount("deposit")? {
+            vault = vault.deposit(deposit)?;
+            println!("{:<11}: {:>9.4} {}", "deposit", deposit, symbol);
+        }
+        if let Some(payback) = amount("payback")? {
+            vault = vault.payback(payback, &limits)?;
+            println!("{:<11}: {:>9.2} dai", "payback", payback);
+        }
+        if let Some(withdraw) = amount("withdraw")? {
+            vault = vault.withdraw(withdraw, price, &limits)?;
+            println!("{:<11}: {:>9.4} {}", "withdraw", withdraw, symbol);
+        }
+        if let Some(draw) = amount("draw")? {
+            vault = vault.draw(draw, price, &limits)?;
+            println!("{:<11}: {:>9.2} dai", "draw", draw);
+        }
+        let terms = Dog::new(&reader)?.get_liquidation_terms(&ilk_id).await?;
+        vault.show(price, get_btc_price(&reader).await?, &terms)?;
+    } else if let Some(matches) = matches.subcommand_matches("close") {
+        if !matches.is_present("dry_run") {
+            return Err("closing is only planned, run it again with --dry-run".into());
+        }
+        let vault_id = value_t_or_exit!(matches.value_of("VAULT_ID"), u128);
+        let flash_fee = value_t_or_exit!(matches.value_of("flash_fee"), f64);
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        let saver = Saver::new(&reader)?;
+        let vault_id = Uint::from(vault_id);
+        let price = get_price(&reader, vault_id, matches).await?;
+        let vault = saver.get_vault(vault_id).await?;
+        let pool = Factory::new(&reader)?
+            .get_pool(&DAI_ADDRESS.parse()?, &vault.token.address)
+            .await?;
+        let cdp_manager = CdpManager::new(&reader)?;
+        let ilk_id = cdp_manager.get_ilk_id(vault_id).await?;
+        let limits = saver
+            .get_ilk_info(&ilk_id, vault_id)
+            .await?
+            .get_vat_limits()?;
+        let dai = match matches.is_present("dai") {
+            true => Some(Wad::from_f64(value_t_or_exit!(
+                matches.value_of("dai"),
+                f64
+            ))?),
+            false => None,
+        };
+        let symbol = vault.token.symbol.to_lowercase();
+        println!(
+            "{:<11}: {:>12.4} {} / {:.2} dai at {:.2} (pool at {:.2})",
+            "vault",
+            vault.collateral,
+            symbol,
+            vault.debt,
+            price,
+            pool.get_price(vault.token.decimals)?
+        );
+        for unwind in [Unwind::FlashLoan, Unwind::Iterative].iter() {
+            println!();
+            let flash_fee = Wad::from_f64(flash_fee / 100.0)?;
+            let plan = match get_close_plan(&vault, dai, price, &pool, &limits, *unwind, flash_fee)
+            {
+                Ok(plan) => plan,
+                Err(e) => {
+                    println!("{}: {}", unwind, e);
+                    continue;
+                }
+            };
+            println!("{}: {} step(s)", unwind, plan.steps.len());
+            for step in &plan.steps {
+                let ratio = match step.ratio {
+                    Some(ratio) => format!("{:.2}%", ratio.to_percent()),
+                    None => String::from("no debt"),
+                };
+                println!(
+                    "  sell {:>10.4} {} for {:>12.2} dai, debt {:>12.2} ({})",
+                    step.collateral_sold, symbol, step.dai_received, step.debt, ratio
+                );
+            }
+            println!("{:<11}: {:>12.2} dai", "repaid", plan.debt_repaid);
+            println!("{:<11}: {:>12.4} {}", "sold", plan.collateral_sold, symbol);
+            println!("{:<11}: {:>12.2} dai", "fees", plan.fees);
+            println!("{:<11}: {:>12.2} dai", "slippage", plan.slippage);
+            if *unwind == Unwind::FlashLoan {
+                println!("{:<11}: {:>12.2} dai", "loan fee", plan.flash_loan_fee);
+            }
+            println!("{:<11}: {:>12.2} dai", "total cost", plan.get_cost()?);
+            println!(
+                "{:<11}: {:>12.4} {} / {:.2} dai",
+                "left", plan.vault.collateral, symbol, plan.vault.debt
+            );
+        }
+    } else if let Some(matches) = matches.subcommand_matches("leverage") {
+        let vault_id = value_t_or_exit!(matches.value_of("VAULT_ID"), u128);
+        let target_ratio = Wad::from_percent(value_t_or_exit!(matches.value_of("RATIO"), usize));
+        let flash_fee = value_t_or_exit!(matches.value_of("flash_fee"), f64);
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        let saver = Saver::new(&reader)?;
+        let vault_id = Uint::from(vault_id);
+        let price = get_price(&reader, vault_id, matches).await?;
+        let vault = saver.get_vault(vault_id).await?;
+        let pool = Factory::new(&reader)?
+            .get_pool(&DAI_ADDRESS.parse()?, &vault.token.address)
+            .await?;
+        let cdp_manager = CdpManager::new(&reader)?;
+        let ilk_id = cdp_manager.get_ilk_id(vault_id).await?;
+        let limits = saver
+            .get_ilk_info(&ilk_id, vault_id)
+            .await?
+            .get_vat_limits()?;
+        let symbol = vault.token.symbol.to_lowercase();
+        println!(
+            "{:<11}: {:>12.4} {} / {:.2} dai at {:.2} (pool at {:.2})",
+            "vault",
+            vault.collateral,
+            symbol,
+            vault.debt,
+            price,
+            pool.get_price(vault.token.decimals)?
+        );
+        for leverage in [Leverage::FlashLoan, Leverage::Iterative].iter() {
+            println!();
+            let flash_fee = Wad::from_f64(flash_fee / 100.0)?;
+            let plan = match get_leverage_plan(
+                &vault,
+                target_ratio,
+                price,
+                &pool,
+                &limits,
+                *leverage,
+                flash_fee,
+            ) {
+                Ok(plan) => plan,
+                Err(e) => {
+                    println!("{}: {}", leverage, e);
+                    continue;
+                }
+            };
+            println!("{}: {} step(s)", leverage, plan.steps.len());
+            for step in &plan.steps {
+                println!(
+                    "  buy {:>10.4} {} for {:>12.2} dai, debt {:>12.2} ({:.2}%)",
+                    step.collateral_bought,
+                    symbol,
+                    step.dai_sold,
+                    step.debt,
+                    step.ratio.to_percent()
+                );
+            }
+            println!("{:<11}: {:>12.2} dai", "minted", plan.dai_drawn);
+            println!(
+                "{:<11}: {:>12.4} {}",
+                "bought", plan.collateral_bought, symbol
+            );
+            println!("{:<11}: {:>12.2} dai", "fees", plan.fees);
+            println!("{:<11}: {:>12.2} dai", "slippage", plan.slippage);
+            if *leverage == Leverage::FlashLoan {
+                println!("{:<11}: {:>12.2} dai", "loan fee", plan.flash_loan_fee);
+            }
+            println!("{:<11}: {:>11.2}%", "ratio", plan.ratio.to_percent());
+            println!(
+                "{:<11}: {:>12.2} ({}%)",
+                "liquidation",
+                plan.liquidation_price,
+                limits.liquidation_ratio.to_percent()
+            );
+        }
+    } else if let Some(matches) = matches.subcommand_matches("exposure") {
+        let vault_id = value_t_or_exit!(matches.value_of("VAULT_ID"), u128);
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        let saver = Saver::new(&reader)?;
+        let vault_id = Uint::from(vault_id);
+        let price = get_price(&reader, vault_id, matches).await?;
+        let vault = saver.get_vault(vault_id).await?;
+        let eth_price = match vault.token.symbol == "WETH" {
+            true => price,
+            false => {
+                let dai_eth_pair = Pair::new(&reader, DAI_ETH_PAIR_ADDRESS.parse()?)?;
+                let dai_eth_pool = dai_eth_pair.get_pool(&DAI_ADDRESS.parse()?).await?;
+                Wad(dai_eth_pool.dai_reserve).checked_div(Wad(dai_eth_pool.col_reserve))?
+            }
+        };
+        let mut addresses = vec![vault.owner];
+        let proxy_registry = ProxyRegistry::new(&reader)?;
+        if let Some(proxy_owner) = proxy_registry.get_owner(&vault.owner).await? {
+            addresses.push(proxy_owner);
+        }
+        let exposure = Exposure::load(&reader, vault, &addresses).await?;
+        exposure.show(price, eth_price)?;
+    } else if let Some(matches) = matches.subcommand_matches("list") {
+        let address = parse_address(matches.value_of("ADDRESS").unwrap())?;
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        let cdp_manager = CdpManager::new(&reader)?;
+        let proxy_registry = ProxyRegistry::new(&reader)?;
+        let mut vault_ids = cdp_manager.get_vault_ids(&address).await?;
+        if let Some(proxy) = proxy_registry.get_proxy(&address).await? {
+            vault_ids.append(&mut cdp_manager.get_vault_ids(&proxy).await?);
+        }
+        print_vaults(&reader, vault_ids).await?;
+    } else if let Some(matches) = matches.subcommand_matches("permissions") {
+        let vault_id = value_t_or_exit!(matches.value_of("VAULT_ID"), u128);
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        let cdp_manager = CdpManager::new(&reader)?;
+        let vault_id = Uint::from(vault_id);
+        println!("{:<16}: {:#x}", "urn", cdp_manager.get_urn(vault_id).await?);
+        for permission in get_permissions(&reader, vault_id).await? {
+            println!("{:<16}: {:#x}", permission.access, permission.address);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("subscribers") {
+        let per_page = value_t_or_exit!(matches.value_of("per_page"), usize);
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        let saver = Saver::new(&reader)?;
+        let token = matches.value_of("token");
+        let price = match matches.is_present("price") {
+            true => {
+                let price_f64 = value_t_or_exit!(matches.value_of("price"), f64);
+                Some(Wad::from_f64(price_f64)?)
+            }
+            false => None,
+        };
+        let mut vaults = Vec::new();
+        for vault_id in saver.get_subscribed_ids(per_page).await? {
+            match saver.get_vault(vault_id).await {
+                Ok(vault) => {
+                    if token.is_none() || token == Some(vault.token.symbol.as_str()) {
+                        vaults.push(vault);
+                    }
+                }
+                Err(e) => eprintln!("skipping vault {}: {}", vault_id, e),
+            }
+        }
+        for stats in SubscriberStats::new(vaults)? {
+            stats.show(price)?;
+        }
+    } else if let Some(matches) = matches.subcommand_matches("history-settings") {
+        let vault_id = value_t_or_exit!(matches.value_of("VAULT_ID"), u128);
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        let saver = Saver::new(&reader)?;
+        for change in saver.get_settings_history(Uint::from(vault_id)).await? {
+            println!(
+                "{:>9} {} {:<12} {:>4}% {:>4}% {:>4}% {:>4}% {:<5} {:#x}",
+                change.block_number,
+                format_timestamp(change.timestamp),
+                change.action,
+                change.min_ratio.to_percent(),
+                change.repay_ratio.to_percent(),
+                change.boost_ratio.to_percent(),
+                change.max_ratio.to_percent(),
+                match change.boost_enabled {
+                    true => "boost",
+                    false => "",
+                },
+                change.transaction_hash,
+            );
+        }
+    } else if let Some(matches) = matches.subcommand_matches("history-actions") {
+        let vault_id = value_t_or_exit!(matches.value_of("VAULT_ID"), u128);
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        for report in get_execution_reports(&reader, Some(Uint::from(vault_id))).await? {
+            let execution = &report.execution;
+            let predicted_dai = match &report.predicted {
+                Some(predicted) => {
+                    let debt_change = match predicted.debt > report.vault.debt {
+                        true => predicted.debt.checked_sub(report.vault.debt)?,
+                        false => report.vault.debt.checked_sub(predicted.debt)?,
+                    };
+                    format!("{:.2}", debt_change)
+                }
+                None => "-".to_string(),
+            };
+            println!(
+                "{:>9} {} {:<5} {:>12.4} {} {:>12.2} dai (predicted {:>10}) gas {:.4} eth @ {:.2} friction {:.2}% {:#x}",
+                execution.block_number,
+                format_timestamp(execution.timestamp),
+                execution.action,
+                Wad::from_decimals(execution.collateral, report.vault.token.decimals)?,
+                report.vault.token.symbol.to_lowercase(),
+                execution.dai,
+                predicted_dai,
+                execution.get_gas_fee(),
+                report.price,
+                report.friction * 100.0,
+                execution.transaction_hash,
+            );
+        }
+    } else if let Some(matches) = matches.subcommand_matches("history-liquidations") {
+        let vault_id = value_t_or_exit!(matches.value_of("VAULT_ID"), u128);
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        println!(
+            "note: only the Dog liquidations are read, the Cat ones before April 2021 are not"
+        );
+        for report in get_liquidation_reports(&reader, Uint::from(vault_id)).await? {
+            let bark = &report.bark;
+            let estimated = &report.estimated;
+            let (sold, returned) = match report.get_collateral_returned() {
+                Some(returned) => (
+                    format!("{:.4}", bark.ink.saturating_sub(returned)),
+                    format!("{:.4}", returned),
+                ),
+                None => ("-".to_string(), "in auction".to_string()),
+            };
+            println!(
+                "{:>9} {} {:>12.2} dai {:>12.4} col @ {:.2} sold {:>10} (estimated {:>10.4}) returned {:>10} (estimated {:>10.4}) {:#x}",
+                bark.block_number,
+                format_timestamp(bark.timestamp),
+                bark.due.to_wad(),
+                bark.ink,
+                report.oracle_price,
+                sold,
+                estimated.collateral_sold,
+                returned,
+                estimated.collateral_returned,
+                bark.transaction_hash,
+            );
+        }
+    } else if let Some(matches) = matches.subcommand_matches("calibrate") {
+        let vault_id = match matches.is_present("VAULT_ID") {
+            true => Some(Uint::from(value_t_or_exit!(
+                matches.value_of("VAULT_ID"),
+                u128
+            ))),
+            false => None,
+        };
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        let reports = get_execution_reports(&reader, vault_id).await?;
+        let calibration = Calibration::fit(&reports)?;
+        for (action, cost, count) in [
+            ("boost", calibration.boost, calibration.boosts),
+            ("repay", calibration.repay, calibration.repays),
+        ] {
+            match cost {
+                Some(cost) => println!(
+                    "{:<5}: {:>4} executions, {:.2} dai of gas + {:.2}% slippage",
+                    action,
+                    count,
+                    cost.gas,
+                    cost.slippage * 100.0
+                ),
+                None => println!("{:<5}: no execution to fit on", action),
+            }
+        }
+        if let (Some(boost), Some(repay)) = (calibration.boost, calibration.repay) {
+            println!(
+                "--boost_gas {:.2} --boost_slippage {:.4} --repay_gas {:.2} --repay_slippage {:.4}",
+                boost.gas, boost.slippage, repay.gas, repay.slippage
+            );
+        }
+    } else if let Some(matches) = matches.subcommand_matches("montecarlo") {
+        let vault_id = value_t_or_exit!(matches.value_of("VAULT_ID"), u128);
+        let friction = value_t_or_exit!(matches.value_of("friction"), f64);
+        let friction = get_friction(matches, friction)?;
+        let monte_carlo = MonteCarlo {
+            drift: value_t_or_exit!(matches.value_of("drift"), f64),
+            volatility: value_t_or_exit!(matches.value_of("volatility"), f64),
+            days: value_t_or_exit!(matches.value_of("days"), f64),
+            steps: value_t_or_exit!(matches.value_of("steps"), usize),
+            paths: value_t_or_exit!(matches.value_of("paths"), usize),
+            seed: value_t_or_exit!(matches.value_of("seed"), u64),
+        };
+        let node = matches.value_of("NODE").unwrap();
+        let transport = HttpTransport::new(node);
+        let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+        let saver = Saver::new(&reader)?;
+        let cdp_manager = CdpManager::new(&reader)?;
+        let vault_id = Uint::from(vault_id);
+        let ilk_id = cdp_manager.get_ilk_id(vault_id).await?;
+        let price = get_ilk_price(&reader, &ilk_id, false).await?;
+        let terms = Dog::new(&reader)?.get_liquidation_terms(&ilk_id).await?;
+        let mut vault = saver.get_vault(vault_id).await?;
+        override_ratios(&mut vault, matches);
+        vault.close_rules = get_close_rules(matches, price)?;
+        let report = monte_carlo.run(&vault, price, &friction, get_delay(matches), &terms)?;
+        println!("{:<13}: {:>12.2} dai", "start", vault.get_equity(price)?);
+        println!("{:<13}: {:>12.2} dai", "mean", report.get_mean());
+        for percentile in &[5.0, 25.0, 50.0, 75.0, 95.0] {
+            println!(
+                "{:<13}: {:>12.2} dai",
+                format!("percentile {}", percentile),
+                report.get_percentile(*percentile)
+            );
+        }
+        println!(
+            "{:<13}: {:>11.2}%",
+            "liquidation",
+            report.liquidation_probability * 100.0
+        );
+        println!(
+            "{:<13}: {:>12.2} dai",
+            "penalty loss", report.average_penalty_loss
+        );
+        println!(
+            "{:<13}: {:>11.2}%",
+            "closed",
+            report.close_probability * 100.0
+        );
+        println!("{:<13}: {:>12.2}", "actions", report.average_actions);
+        if let Some(without_rules) = &report.without_rules {
+            println!(
+                "{:<13}: {:>12.2} dai without the close rules (median {:.2}, liquidation {:.2}%)",
+                "mean",
+                without_rules.get_mean(),
+                without_rules.get_median(),
+                without_rules.liquidation_probability * 100.0
+            );
+        }
+        if report.failed_paths > 0 {
+            println!(
+                "{:<13}: {:>12} of {}, not counted",
+                "failed paths", report.failed_paths, monte_carlo.paths
+            );
+        }
+    } else if let Some(matches) = matches.subcommand_matches("backtest") {
+        let friction = value_t_or_exit!(matches.value_of("friction"), f64);
+        let friction = get_friction(matches, friction)?;
+        let capital = value_t_or_exit!(matches.value_of("capital"), f64);
+        let mut settings = get_default_vault()?;
+        override_ratios(&mut settings, matches);
+        let start_ratio = match matches.is_present("start_ratio") {
+            true => Wad::from_percent(value_t_or_exit!(matches.value_of("start_ratio"), usize)),
+            false => settings.boost_ratio,
+        };
+
+        let (ticks, terms) = match matches.value_of("csv") {
+            Some(path) => {
+                let liquidation_ratio =
+                    value_t_or_exit!(matches.value_of("liquidation_ratio"), usize);
+                let terms = LiquidationTerms {
+                    ratio: Wad::from_percent(liquidation_ratio),
+                    penalty: get_penalty(matches),
+                    auction: None,
+                };
+                (read_ticks(&std::fs::read_to_string(path)?)?, terms)
+            }
+            None => {
+                let node = matches.value_of("NODE").unwrap();
+                let transport = HttpTransport::new(node);
+                let reader: HttpBlockchainReader = HttpBlockchainReader::new(transport)?;
+                let ilk = matches.value_of("osm").unwrap();
+                let mut ilk_id = [0u8; 32];
+                if ilk.len() > ilk_id.len() {
+                    return Err(format!("{} is not a valid ilk", ilk).into());
+                }
+                ilk_id[..ilk.len()].copy_from_slice(ilk.as_bytes());
+                let from_block = match matches.is_present("from_block") {
+                    true => value_t_or_exit!(matches.value_of("from_block"), u64),
+                    false => MCD_DEPLOYMENT_BLOCK,
+                };
+                let to_block = match matches.is_present("to_block") {
+                    true => BlockNumber::Number(
+                        value_t_or_exit!(matches.value_of("to_block"), u64).into(),
+                    ),
+                    false => BlockNumber::Latest,
+                };
+                let spot = Spot::new(&reader)?;
+                let median = Median::new(&reader, spot.get_median_address(&ilk_id).await?)?;
+                let terms = Dog::new(&reader)?.get_liquidation_terms(&ilk_id).await?;
+                (median.get_price_history(from_block, to_block).await?, terms)
+            }
+        };
+        settings.check_ratios(Wad::zero(), terms.ratio)?;
+        let (first, last) = match (ticks.first(), ticks.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err("no price to backtest".into()),
+        };
+        let mut vault =
+            get_start_vault(&settings, first.price, Wad::from_f64(capital)?, start_ratio)?;
+        vault.close_rules = get_close_rules(matches, first.price)?;
+        let backtest = backtest(&vault, &ticks, &friction, get_delay(matches), &terms)?;
+        let start_equity = backtest.start_equity.to_f64();
+        let period = match (first.timestamp, last.timestamp) {
+            (Some(start), Some(end)) => {
+                format!("{} -> {}", format_timestamp(start), format_timestamp(end))
+            }
+            _ => String::from("-"),
+        };
+        println!("{:<12}: {} ({} prices)", "period", period, ticks.len());
+        println!(
+            "{:<12}: {:>12.2} -> {:>12.2} dai/col",
+            "price", first.price, last.price
+        );
+        println!("{:<12}: {:>12.2} dai", "start", start_equity);
+        let equities = [
+            ("automated", backtest.result.get_final_equity().to_f64()),
+            ("hodl", backtest.hodl_equity.to_f64()),
+            ("unautomated", backtest.unautomated_equity.to_f64()),
+        ];
+        for (name, equity) in &equities {
+            println!(
+                "{:<12}: {:>12.2} dai (x{:.2})",
+                name,
+                equity,
+                equity / start_equity
+            );
+        }
+        if let Some(without_rules) = &backtest.without_rules {
+            let equity = without_rules.get_final_equity().to_f64();
+            println!(
+                "{:<12}: {:>12.2} dai (x{:.2}) without the close rules",
+                "automated",
+                equity,
+                equity / start_equity
+            );
+        }
+        println!(
+            "{:<12}: {:>11.2}%",
+            "max drawdown",
+            backtest.max_drawdown * 100.0
+        );
+        println!(
+            "{:<12}: {} boosts, {} repays",
+            "actions", backtest.result.boosts, backtest.result.repays
+        );
+        match backtest.result.lowest_ratio {
+            Some(lowest_ratio) => {
+                println!(
+                    "{:<12}: {:>11.2}%",
+                    "lowest ratio",
+                    lowest_ratio.to_percent()
+                )
+            }
+            None => println!("{:<12}: no debt", "lowest ratio"),
+        }
+        if let Some(close) = &backtest.result.close {
+            println!(
+                "closed by the {} at {:.2}: {:.4} collateral sold at {:.2}, friction {:.2} dai",
+                close.rule, close.trigger_price, close.collateral_sold, close.price, close.friction
+            );
+            if !close.shortfall.is_zero() {
+                println!(
+                    "the collateral left {:.2} dai of debt unpaid, the vault is lost",
+                    close.shortfall
+                );
+            }
+        }
+        if let Some(liquidation) = &backtest.result.liquidation {
+            println!(
+                "liquidated at {:.2} (oracle at {:.2}, sold at {:.2}): {:.2} dai of debt, {:.4} collateral sold, {:.4} returned, {:.2} dai lost to the penalty",
+                liquidation.price,
+                liquidation.trigger_price,
+                liquidation.sale_price,
+                liquidation.debt,
+                liquidation.collateral_sold,
+                liquidation.collateral_returned,
+                liquidation.penalty_loss
+            );
+        }
     } else if let Some(matches) = matches.subcommand_matches("optimize") {
         let friction = value_t_or_exit!(matches.value_of("friction"), f64);
+        let friction = get_friction(matches, friction)?;
         let increase = value_t_or_exit!(matches.value_of("increase"), f64);
         let start = value_t_or_exit!(matches.value_of("start"), usize);
         let end = value_t_or_exit!(matches.value_of("end"), usize);
         let list = matches.is_present("list");
+        let close_rules = get_close_rules(matches, Wad::from_f64(SIMULATION_START_PRICE)?)?;
+        for rule in &close_rules {
+            println!("close: {}", rule);
+        }
         let mut scenarios = Vec::with_capacity(end - start + 1);
         for boost in start..=end {
-            let scenario = get_simulation(boost, increase, friction, 140)?;
+            let scenario = get_simulation(boost, increase, &friction, 140, &close_rules)?;
             scenarios.push(scenario);
         }
         print_scenarios(scenarios, !list);
@@ -105,12 +829,331 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+async fn get_price(
+    reader: &HttpBlockchainReader,
+    vault_id: Uint,
+    matches: &ArgMatches<'_>,
+) -> Result<Wad, Box<dyn Error>> {
+    if matches.is_present("price") {
+        let price_f64 = value_t_or_exit!(matches.value_of("price"), f64);
+        return Wad::from_f64(price_f64);
+    }
+    let cdp_manager: CdpManager = CdpManager::new(reader)?;
+    let next = matches.is_present("next");
+    let ilk_id = cdp_manager.get_ilk_id(vault_id).await?;
+    get_ilk_price(reader, &ilk_id, next).await
+}
+
+/// The BTC value of one DAI, from the uniswap WBTC / ETH and DAI / ETH pairs.
+async fn get_btc_price(reader: &HttpBlockchainReader) -> Result<f64, Box<dyn Error>> {
+    let wbtc_eth_pair_address: Address = "Bb2b8038a1640196FbE3e38816F3e67Cba72D940".parse()?;
+    let wbtc_eth_pair = Pair::new(reader, wbtc_eth_pair_address)?;
+    let wbtc_price = wbtc_eth_pair.get_price_0().await?;
+    let dai_eth_pair = Pair::new(reader, DAI_ETH_PAIR_ADDRESS.parse()?)?;
+    let dai_eth_pool = dai_eth_pair.get_pool(&DAI_ADDRESS.parse()?).await?;
+    let eth_price = Wad(dai_eth_pool.dai_reserve)
+        .checked_div(Wad(dai_eth_pool.col_reserve))?
+        .to_f64();
+    Ok(wbtc_price / eth_price)
+}
+
+async fn get_ilk_price(
+    reader: &HttpBlockchainReader,
+    ilk_id: &[u8],
+    next: bool,
+) -> Result<Wad, Box<dyn Error>> {
+    let spot: Spot = Spot::new(reader)?;
+    let median_address = spot.get_median_address(ilk_id).await?;
+    let median = Median::new(reader, median_address)?;
+    median.get_price(next).await
+}
+
+/// Applies the `--min_ratio`, `--repay_ratio`, `--boost_ratio` and `--max_ratio` overrides,
+/// returns whether any was given.
+fn override_ratios(vault: &mut Vault, matches: &ArgMatches<'_>) -> bool {
+    let ratios = ["max_ratio", "boost_ratio", "min_ratio", "repay_ratio"];
+    if matches.is_present("max_ratio") {
+        let max_ratio_pc = value_t_or_exit!(matches.value_of("max_ratio"), usize);
+        vault.max_ratio = Wad::from_percent(max_ratio_pc);
+    }
+    if matches.is_present("boost_ratio") {
+        let boost_ratio_pc = value_t_or_exit!(matches.value_of("boost_ratio"), usize);
+        vault.boost_ratio = Wad::from_percent(boost_ratio_pc);
+    }
+    if matches.is_present("min_ratio") {
+        let min_ratio_pc = value_t_or_exit!(matches.value_of("min_ratio"), usize);
+        vault.min_ratio = Wad::from_percent(min_ratio_pc);
+    }
+    if matches.is_present("repay_ratio") {
+        let repay_ratio_pc = value_t_or_exit!(matches.value_of("repay_ratio"), usize);
+        vault.repay_ratio = Wad::from_percent(repay_ratio_pc);
+    }
+    ratios.iter().any(|ratio| matches.is_present(ratio))
+}
+
+/// The calibrated friction when its values are given (see `get_friction_args`), the constant
+/// `friction` otherwise.
+fn get_friction(matches: &ArgMatches<'_>, friction: f64) -> Result<Friction, Box<dyn Error>> {
+    if !matches.is_present("boost_gas") {
+        return Ok(Friction::Constant(friction));
+    }
+    let value = |arg: &str| value_t_or_exit!(matches.value_of(arg), f64);
+    Ok(Friction::Calibrated {
+        boost: TradeCost {
+            gas: Wad::from_f64(value("boost_gas"))?,
+            slippage: value("boost_slippage"),
+        },
+        repay: TradeCost {
+            gas: Wad::from_f64(value("repay_gas"))?,
+            slippage: value("repay_slippage"),
+        },
+    })
+}
+
+/// The OSM delay when `--delayed` is present, none otherwise.
+fn get_delay(matches: &ArgMatches<'_>) -> u64 {
+    match matches.is_present("delayed") {
+        true => OSM_DELAY,
+        false => 0,
+    }
+}
+
+/// The `--penalty`, as the factor the debt is repaid with.
+fn get_penalty(matches: &ArgMatches<'_>) -> Wad {
+    let penalty = value_t_or_exit!(matches.value_of("penalty"), usize);
+    Wad::from_percent(100 + penalty)
+}
+
+fn parse_address(address: &str) -> Result<Address, Box<dyn Error>> {
+    Ok(address.trim_start_matches("0x").parse()?)
+}
+
+fn ilk_name(ilk_id: &[u8]) -> String {
+    let name: Vec<u8> = ilk_id.iter().cloned().take_while(|b| *b != 0).collect();
+    String::from_utf8_lossy(&name).to_string()
+}
+
+async fn print_vaults(
+    reader: &HttpBlockchainReader,
+    vault_ids: Vec<Uint>,
+) -> Result<(), Box<dyn Error>> {
+    let saver = Saver::new(reader)?;
+    let cdp_manager = CdpManager::new(reader)?;
+    let mut prices: HashMap<Vec<u8>, Wad> = HashMap::new();
+    let mut vecs = Vec::with_capacity(vault_ids.len());
+    for vault_id in vault_ids {
+        let ilk_id = cdp_manager.get_ilk_id(vault_id).await?;
+        let price = match prices.get(&ilk_id) {
+            Some(price) => *price,
+            None => {
+                let price = get_ilk_price(reader, &ilk_id, false).await?;
+                prices.insert(ilk_id.clone(), price);
+                price
+            }
+        };
+        let vault = saver.get_vault(vault_id).await?;
+        let ratio = match vault.debt.is_zero() {
+            false => {
+                let ratio = vault
+                    .collateral
+                    .checked_mul(price)?
+                    .checked_div(vault.debt)?;
+                format!("{:.2}%", ratio.to_percent())
+            }
+            true => "-".to_string(),
+        };
+        vecs.push(vec![
+            format!("{}", vault_id).cell(),
+            ilk_name(&ilk_id).cell(),
+            format!(
+                "{:.4} {}",
+                vault.collateral,
+                vault.token.symbol.to_lowercase()
+            )
+            .cell()
+            .justify(Justify::Right),
+            format!("{:.2} dai", vault.debt)
+                .cell()
+                .justify(Justify::Right),
+            ratio.cell().justify(Justify::Right),
+            match vault.subscribed {
+                true => "yes",
+                false => "no",
+            }
+            .cell(),
+        ]);
+    }
+    let table = vecs
+        .table()
+        .title(vec![
+            "Vault".cell().bold(true),
+            "Ilk".cell().bold(true),
+            "Collateral".cell().bold(true),
+            "Debt".cell().bold(true),
+            "Ratio".cell().bold(true),
+            "Saver".cell().bold(true),
+        ])
+        .bold(true);
+
+    print_stdout(table)?;
+    Ok(())
+}
+
+/// Formats a unix timestamp as an UTC date and time.
+fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// The calibrated friction arguments read by `get_friction`, given all together or not at all.
+fn get_friction_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("boost_gas")
+            .long("boost_gas")
+            .takes_value(true)
+            .requires_all(&["boost_slippage", "repay_gas", "repay_slippage"])
+            .help("if present with the other calibrated values, the gas paid by a boost (in DAI, see calibrate)"),
+        Arg::with_name("boost_slippage")
+            .long("boost_slippage")
+            .takes_value(true)
+            .requires_all(&["boost_gas", "repay_gas", "repay_slippage"])
+            .help("the share of value lost by a boost (see calibrate)"),
+        Arg::with_name("repay_gas")
+            .long("repay_gas")
+            .takes_value(true)
+            .requires_all(&["boost_gas", "boost_slippage", "repay_slippage"])
+            .help("the gas paid by a repay (in DAI, see calibrate)"),
+        Arg::with_name("repay_slippage")
+            .long("repay_slippage")
+            .takes_value(true)
+            .requires_all(&["boost_gas", "boost_slippage", "repay_gas"])
+            .help("the share of value lost by a repay (see calibrate)"),
+    ]
+}
+
+/// The arguments read by `get_close_rules`, shared by the commands that simulate a vault.
+fn get_close_rule_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("stop_loss")
+            .long("stop_loss")
+            .takes_value(true)
+            .help("if present, close the vault once the price falls to this price"),
+        Arg::with_name("take_profit")
+            .long("take_profit")
+            .takes_value(true)
+            .help("if present, close the vault once the price rises to this price"),
+        Arg::with_name("trailing_stop_loss")
+            .long("trailing_stop_loss")
+            .takes_value(true)
+            .help("if present, close the vault once the price falls this far below its high (in %)"),
+        Arg::with_name("trailing_take_profit")
+            .long("trailing_take_profit")
+            .takes_value(true)
+            .help("if present, once the price went past this price, close the vault when it falls --trailing_distance below its high"),
+        Arg::with_name("trailing_distance")
+            .long("trailing_distance")
+            .takes_value(true)
+            .default_value("10")
+            .help("The fall below the high that closes a trailing take-profit (in %)"),
+        Arg::with_name("close_to")
+            .long("close_to")
+            .takes_value(true)
+            .default_value("dai")
+            .help("What the close rules turn the vault into: dai or collateral"),
+    ]
+}
+
+/// The stop-loss and take-profit rules given in `matches`, the trailing ones starting from
+/// `price`.
+fn get_close_rules(matches: &ArgMatches<'_>, price: Wad) -> Result<Vec<CloseRule>, Box<dyn Error>> {
+    let to = match matches.value_of("close_to") {
+        Some("collateral") => CloseTo::Collateral,
+        Some("dai") | None => CloseTo::Dai,
+        Some(to) => return Err(format!("cannot close to {}, only to dai or collateral", to).into()),
+    };
+    let value = |arg: &str| value_t_or_exit!(matches.value_of(arg), f64);
+    let mut triggers = Vec::new();
+    if matches.is_present("stop_loss") {
+        triggers.push(CloseTrigger::StopLoss(Wad::from_f64(value("stop_loss"))?));
+    }
+    if matches.is_present("take_profit") {
+        triggers.push(CloseTrigger::TakeProfit(Wad::from_f64(value(
+            "take_profit",
+        ))?));
+    }
+    if matches.is_present("trailing_stop_loss") {
+        triggers.push(CloseTrigger::TrailingStopLoss {
+            distance: Wad::from_f64(value("trailing_stop_loss") / 100.0)?,
+            high: price,
+        });
+    }
+    if matches.is_present("trailing_take_profit") {
+        triggers.push(CloseTrigger::TrailingTakeProfit {
+            activation: Wad::from_f64(value("trailing_take_profit"))?,
+            distance: Wad::from_f64(value("trailing_distance") / 100.0)?,
+            high: price,
+        });
+    }
+    Ok(triggers
+        .into_iter()
+        .map(|trigger| CloseRule { trigger, to })
+        .collect())
+}
+
+fn print_trace(trace: &Trace) {
+    for step in &trace.steps {
+        let ratio = match step.ratio {
+            Some(ratio) => format!("{:.2}%", ratio.to_percent()),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<5} at {:>9.2}: {:>12.2} dai {:>12.4} col, friction {:>9.2} dai -> {}",
+            step.action, step.trigger_price, step.dai, step.collateral, step.friction, ratio
+        );
+    }
+    if trace.stalled {
+        println!("stopped: the last boost moved the up price by less than 1%");
+    }
+    if let Some(close) = &trace.close {
+        println!(
+            "closed by the {} at {:.2}: {:.4} col sold at {:.2}, friction {:.2} dai",
+            close.rule, close.trigger_price, close.collateral_sold, close.price, close.friction
+        );
+        if !close.shortfall.is_zero() {
+            println!(
+                "the collateral left {:.2} dai of debt unpaid, the vault is lost",
+                close.shortfall
+            );
+        }
+    }
+}
+
 fn print_scenarios(scenarios: Vec<Scenario>, table: bool) {
     if table {
         let mut vecs = Vec::with_capacity(scenarios.len());
         let increase = &scenarios[0].clone().price_increase;
+        let with_rules = scenarios[0].without_rules.is_some();
         for scenario in scenarios {
-            vecs.push(vec![
+            let mut row = vec![
                 scenario.boost_ratio.cell(),
                 format!("x{:.2}", scenario.no_boost_increase)
                     .cell()
@@ -121,20 +1164,35 @@ fn print_scenarios(scenarios: Vec<Scenario>, table: bool) {
                 format!("{}", scenario.best_result.max_ratio)
                     .cell()
                     .justify(Justify::Right),
-            ])
+            ];
+            if let Some(without_rules) = &scenario.without_rules {
+                row.push(
+                    format!("x{:.2}", without_rules.best_result.increase)
+                        .cell()
+                        .justify(Justify::Right),
+                );
+                row.push(
+                    format!("{}", without_rules.best_result.max_ratio)
+                        .cell()
+                        .justify(Justify::Right),
+                );
+            }
+            vecs.push(row);
         }
 
         println!("What happens when the price makes x{:.2}?", increase);
         println!("If you hodl, you will make x{:.2}.", increase);
-        let table = vecs
-            .table()
-            .title(vec![
-                "CDP ratio".cell().bold(true),
-                "No boost".cell().bold(true),
-                "Best boost".cell().bold(true),
-                "Best ratio".cell().bold(true),
-            ])
-            .bold(true);
+        let mut title = vec![
+            "CDP ratio".cell().bold(true),
+            "No boost".cell().bold(true),
+            "Best boost".cell().bold(true),
+            "Best ratio".cell().bold(true),
+        ];
+        if with_rules {
+            title.push("Without rules".cell().bold(true));
+            title.push("Ratio without".cell().bold(true));
+        }
+        let table = vecs.table().title(title).bold(true);
 
         assert!(print_stdout(table).is_ok());
     } else {
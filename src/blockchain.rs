@@ -1,15 +1,19 @@
 use async_jsonrpc_client::HttpTransport;
-use async_jsonrpc_client::{Params, Transport, Value};
-use ethabi::{Address, Contract, Token, Uint};
+use async_jsonrpc_client::{Params, RpcError, Transport, Value};
+use ethabi::{Address, Contract, Function, Hash, Token, Uint};
 use rustc_hex::FromHex;
-use serde_json::json;
 use std::error::Error;
 use std::fmt;
+use web3::signing::keccak256;
 use web3::types::Bytes;
 use web3::types::CallRequest;
+use web3::types::{BlockNumber, Filter, FilterBuilder, Log, Transaction, TransactionReceipt};
 
 use async_trait::async_trait;
 
+/// The widest block range of a single `eth_getLogs`, most providers refuse more.
+const LOGS_BLOCK_RANGE: u64 = 10_000;
+
 #[async_trait]
 pub trait BlockchainReader {
     async fn call_function(
@@ -20,11 +24,99 @@ pub trait BlockchainReader {
         params: &[Token],
     ) -> Result<Vec<Token>, Box<dyn Error>>;
 
+    async fn call_function_at(
+        &self,
+        contract: &Contract,
+        contract_address: &Address,
+        name: &str,
+        params: &[Token],
+        block: BlockNumber,
+    ) -> Result<Vec<Token>, Box<dyn Error>>;
+
     async fn get_storage_at(
         &self,
         address: &Address,
         position: Uint,
     ) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    async fn get_storage_at_block(
+        &self,
+        address: &Address,
+        position: Uint,
+        block: BlockNumber,
+    ) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    async fn get_balance(&self, address: &Address) -> Result<Uint, Box<dyn Error>>;
+
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Box<dyn Error>>;
+
+    /// The logs of `filter`, whatever its blocks, from `from_block` to `to_block` read range
+    /// by range, as the node providers cap the range or the size of a single `eth_getLogs`.
+    async fn get_logs_by_range(
+        &self,
+        filter: &FilterBuilder,
+        from_block: u64,
+        to_block: BlockNumber,
+    ) -> Result<Vec<Log>, Box<dyn Error>>;
+
+    async fn get_block_number(&self) -> Result<u64, Box<dyn Error>>;
+
+    async fn get_block_timestamp(&self, block_number: u64) -> Result<u64, Box<dyn Error>>;
+
+    async fn get_transaction(&self, hash: &Hash) -> Result<Transaction, Box<dyn Error>>;
+
+    async fn get_transaction_receipt(
+        &self,
+        hash: &Hash,
+    ) -> Result<TransactionReceipt, Box<dyn Error>>;
+}
+
+/// The 4 bytes selector of `function`, as found in calldata and `LogNote` topics.
+pub fn get_selector(function: &Function) -> [u8; 4] {
+    let params: Vec<String> = function
+        .inputs
+        .iter()
+        .map(|param| param.kind.to_string())
+        .collect();
+    let hash = keccak256(format!("{}({})", function.name, params.join(",")).as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Whether `error` is a call output that does not decode with the ABI, as when a contract
+/// does not implement the function the way the ABI says.
+pub fn is_decode_error(error: &(dyn Error + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<ethabi::Error>(),
+        Some(ethabi::Error::InvalidData) | Some(ethabi::Error::Utf8(_))
+    )
+}
+
+/// Whether `error` is the node answering that the call reverted, rather than failing to
+/// answer.
+pub fn is_revert(error: &(dyn Error + 'static)) -> bool {
+    match error.downcast_ref::<RpcError>() {
+        // geth says "execution reverted", openethereum "VM execution error"
+        Some(RpcError::RpcResponse(response)) => {
+            let message = response.message.to_lowercase();
+            message.contains("revert") || message.contains("vm execution error")
+        }
+        _ => false,
+    }
+}
+
+/// Whether the call itself failed, it reverted or its output does not decode, so that the
+/// contract does not answer the function. Transport and node errors are not call failures.
+pub fn is_call_failure(error: &(dyn Error + 'static)) -> bool {
+    is_revert(error) || is_decode_error(error)
+}
+
+/// Whether `error` is the node refusing a request, e.g. a log query returning too many
+/// results, rather than a revert or a transport failure.
+fn is_node_limit(error: &(dyn Error + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<RpcError>(),
+        Some(RpcError::RpcResponse(_))
+    ) && !is_revert(error)
 }
 
 pub struct HttpBlockchainReader {
@@ -45,6 +137,24 @@ impl BlockchainReader for HttpBlockchainReader {
         contract_address: &Address,
         name: &str,
         params: &[Token],
+    ) -> Result<Vec<Token>, Box<dyn Error>> {
+        self.call_function_at(
+            contract,
+            contract_address,
+            name,
+            params,
+            BlockNumber::Latest,
+        )
+        .await
+    }
+
+    async fn call_function_at(
+        &self,
+        contract: &Contract,
+        contract_address: &Address,
+        name: &str,
+        params: &[Token],
+        block: BlockNumber,
     ) -> Result<Vec<Token>, Box<dyn Error>> {
         let function = contract.function(name)?;
         let data = function.encode_input(params)?;
@@ -57,7 +167,7 @@ impl BlockchainReader for HttpBlockchainReader {
             value: None,
             data: Some(Bytes(data)),
         })?;
-        let params = Params::Array(vec![req, json!("latest")]);
+        let params = Params::Array(vec![req, serde_json::to_value(block)?]);
         let response: Value = self.transport.send("eth_call", params).await?;
         let hex_str = &response.as_str().ok_or(BlockchainError(String::from(
             "cannot retrieve response from eth_call",
@@ -71,11 +181,21 @@ impl BlockchainReader for HttpBlockchainReader {
         &self,
         address: &Address,
         position: Uint,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.get_storage_at_block(address, position, BlockNumber::Latest)
+            .await
+    }
+
+    async fn get_storage_at_block(
+        &self,
+        address: &Address,
+        position: Uint,
+        block: BlockNumber,
     ) -> Result<Vec<u8>, Box<dyn Error>> {
         let params = Params::Array(vec![
             Value::String(format!("{:#x}", address)),
             Value::String(format!("{:#x}", position)),
-            Value::String("latest".to_string()),
+            serde_json::to_value(block)?,
         ]);
         let response: Value = self.transport.send("eth_getStorageAt", params).await?;
         let hex_str = &response.as_str().ok_or(BlockchainError(String::from(
@@ -85,6 +205,111 @@ impl BlockchainReader for HttpBlockchainReader {
         let data: Vec<u8> = data.iter().rev().take(16).rev().cloned().collect();
         Ok(data)
     }
+
+    async fn get_balance(&self, address: &Address) -> Result<Uint, Box<dyn Error>> {
+        let params = Params::Array(vec![
+            Value::String(format!("{:#x}", address)),
+            Value::String("latest".to_string()),
+        ]);
+        let response: Value = self.transport.send("eth_getBalance", params).await?;
+        let hex_str = response.as_str().ok_or(BlockchainError(String::from(
+            "cannot retrieve response from eth_getBalance",
+        )))?;
+        let balance: Uint = hex_str[2..].parse()?;
+        Ok(balance)
+    }
+
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Box<dyn Error>> {
+        let params = Params::Array(vec![serde_json::to_value(filter)?]);
+        let response: Value = self.transport.send("eth_getLogs", params).await?;
+        let logs: Vec<Log> = serde_json::from_value(response)?;
+        Ok(logs)
+    }
+
+    async fn get_logs_by_range(
+        &self,
+        filter: &FilterBuilder,
+        from_block: u64,
+        to_block: BlockNumber,
+    ) -> Result<Vec<Log>, Box<dyn Error>> {
+        let to_block = match to_block {
+            BlockNumber::Number(number) => number.as_u64(),
+            _ => self.get_block_number().await?,
+        };
+        let mut logs = Vec::new();
+        let mut from = from_block;
+        let mut range = LOGS_BLOCK_RANGE;
+        while from <= to_block {
+            let to = std::cmp::min(from + range - 1, to_block);
+            let chunk_filter = filter
+                .clone()
+                .from_block(BlockNumber::Number(from.into()))
+                .to_block(BlockNumber::Number(to.into()))
+                .build();
+            match self.get_logs(&chunk_filter).await {
+                Ok(mut chunk) => {
+                    logs.append(&mut chunk);
+                    from = to + 1;
+                    // the busy blocks may be behind, a wider range is tried again
+                    range = std::cmp::min(range * 2, LOGS_BLOCK_RANGE);
+                }
+                // too many logs in the range, a narrower one is tried
+                Err(e) if range > 1 && is_node_limit(&*e) => range /= 2,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(logs)
+    }
+
+    async fn get_block_number(&self) -> Result<u64, Box<dyn Error>> {
+        let response: Value = self
+            .transport
+            .send("eth_blockNumber", Params::Array(Vec::new()))
+            .await?;
+        let hex_str = response.as_str().ok_or(BlockchainError(String::from(
+            "cannot retrieve response from eth_blockNumber",
+        )))?;
+        let block_number = u64::from_str_radix(&hex_str[2..], 16)?;
+        Ok(block_number)
+    }
+
+    async fn get_block_timestamp(&self, block_number: u64) -> Result<u64, Box<dyn Error>> {
+        let params = Params::Array(vec![
+            Value::String(format!("{:#x}", block_number)),
+            Value::Bool(false),
+        ]);
+        let response: Value = self.transport.send("eth_getBlockByNumber", params).await?;
+        let hex_str = response["timestamp"]
+            .as_str()
+            .ok_or(BlockchainError(String::from(
+                "cannot retrieve timestamp from eth_getBlockByNumber",
+            )))?;
+        let timestamp = u64::from_str_radix(&hex_str[2..], 16)?;
+        Ok(timestamp)
+    }
+
+    async fn get_transaction(&self, hash: &Hash) -> Result<Transaction, Box<dyn Error>> {
+        let params = Params::Array(vec![Value::String(format!("{:#x}", hash))]);
+        let response: Value = self
+            .transport
+            .send("eth_getTransactionByHash", params)
+            .await?;
+        let transaction: Transaction = serde_json::from_value(response)?;
+        Ok(transaction)
+    }
+
+    async fn get_transaction_receipt(
+        &self,
+        hash: &Hash,
+    ) -> Result<TransactionReceipt, Box<dyn Error>> {
+        let params = Params::Array(vec![Value::String(format!("{:#x}", hash))]);
+        let response: Value = self
+            .transport
+            .send("eth_getTransactionReceipt", params)
+            .await?;
+        let receipt: TransactionReceipt = serde_json::from_value(response)?;
+        Ok(receipt)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -92,7 +317,7 @@ pub struct BlockchainError(pub String);
 
 impl fmt::Display for BlockchainError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{}", self.0)
     }
 }
 
@@ -101,3 +326,54 @@ impl Error for BlockchainError {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethabi::{decode, ParamType};
+
+    #[test]
+    fn selector_of_the_ds_proxy_execute() {
+        let ds_proxy_abi: &[u8] = include_bytes!("abi/ds_proxy.abi");
+        let ds_proxy_contract = Contract::load(ds_proxy_abi).unwrap();
+        let execute = ds_proxy_contract.function("execute").unwrap();
+        assert_eq!(get_selector(execute), [0x1c, 0xff, 0x79, 0xcd]);
+    }
+
+    #[test]
+    fn a_bytes32_output_is_a_decode_error_for_a_string() {
+        let mut output = vec![0u8; 32];
+        output[..3].copy_from_slice(b"MKR");
+        let error: Box<dyn Error> = decode(&[ParamType::String], &output).unwrap_err().into();
+        assert!(is_decode_error(&*error));
+    }
+
+    #[test]
+    fn a_revert_is_a_call_failure() {
+        let response = async_jsonrpc_client::Error {
+            code: async_jsonrpc_client::ErrorCode::ServerError(-32000),
+            message: String::from("execution reverted"),
+            data: None,
+        };
+        let error: Box<dyn Error> = Box::new(RpcError::RpcResponse(response));
+        assert!(is_revert(&*error));
+        assert!(is_call_failure(&*error));
+    }
+
+    #[test]
+    fn a_node_error_is_not_a_call_failure() {
+        let response = async_jsonrpc_client::Error {
+            code: async_jsonrpc_client::ErrorCode::ServerError(-32005),
+            message: String::from("query returned more than 10000 results"),
+            data: None,
+        };
+        let error: Box<dyn Error> = Box::new(RpcError::RpcResponse(response));
+        assert!(!is_call_failure(&*error));
+    }
+
+    #[test]
+    fn other_errors_are_not_decode_errors() {
+        let error: Box<dyn Error> = Box::new(BlockchainError(String::from("timeout")));
+        assert!(!is_decode_error(&*error));
+    }
+}
@@ -1,8 +1,11 @@
-use super::blockchain::BlockchainReader;
-use ethabi::{Address, Contract, Token, Uint};
+use super::blockchain::{get_selector, BlockchainReader};
+use ethabi::{Address, Contract, Hash, Token, Uint};
 use std::error::Error;
+use web3::types::{BlockNumber, FilterBuilder, Log};
 
 const CDP_MANAGER_ADDRESS: &str = "5ef30b9986345249bc32d8928b7ee64de9435e39";
+/// No MCD contract emitted anything before this block.
+pub const MCD_DEPLOYMENT_BLOCK: u64 = 8928152;
 pub struct CdpManager<'a> {
     blockchain_reader: &'a dyn BlockchainReader,
     cdp_manager_address: Address,
@@ -34,4 +37,220 @@ impl<'a> CdpManager<'a> {
         let ilk_id = ilk_id.unwrap();
         Ok(ilk_id)
     }
+
+    pub async fn get_count(&self, owner: &Address) -> Result<Uint, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.cdp_manager_contract,
+                &self.cdp_manager_address,
+                "count",
+                &[Token::Address(*owner)],
+            )
+            .await?;
+
+        let count = tokens[0].clone().to_uint();
+        Ok(count.unwrap())
+    }
+
+    pub async fn get_first(&self, owner: &Address) -> Result<Uint, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.cdp_manager_contract,
+                &self.cdp_manager_address,
+                "first",
+                &[Token::Address(*owner)],
+            )
+            .await?;
+
+        let first = tokens[0].clone().to_uint();
+        Ok(first.unwrap())
+    }
+
+    pub async fn get_last(&self, owner: &Address) -> Result<Uint, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.cdp_manager_contract,
+                &self.cdp_manager_address,
+                "last",
+                &[Token::Address(*owner)],
+            )
+            .await?;
+
+        let last = tokens[0].clone().to_uint();
+        Ok(last.unwrap())
+    }
+
+    /// The previous and next vaults of the same owner, zero at both ends of the list.
+    pub async fn get_list(&self, vault_id: Uint) -> Result<(Uint, Uint), Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.cdp_manager_contract,
+                &self.cdp_manager_address,
+                "list",
+                &[Token::Uint(vault_id)],
+            )
+            .await?;
+
+        let prev = tokens[0].clone().to_uint();
+        let next = tokens[1].clone().to_uint();
+        Ok((prev.unwrap(), next.unwrap()))
+    }
+
+    pub async fn get_vault_ids(&self, owner: &Address) -> Result<Vec<Uint>, Box<dyn Error>> {
+        let count = self.get_count(owner).await?;
+        let mut vault_ids = Vec::with_capacity(count.as_usize());
+        let mut vault_id = self.get_first(owner).await?;
+        while !vault_id.is_zero() {
+            vault_ids.push(vault_id);
+            let (_, next) = self.get_list(vault_id).await?;
+            vault_id = next;
+        }
+        Ok(vault_ids)
+    }
+
+    pub async fn get_urn(&self, vault_id: Uint) -> Result<Address, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.cdp_manager_contract,
+                &self.cdp_manager_address,
+                "urns",
+                &[Token::Uint(vault_id)],
+            )
+            .await?;
+
+        let urn = tokens[0].clone().to_address();
+        Ok(urn.unwrap())
+    }
+
+    pub async fn get_owner(&self, vault_id: Uint) -> Result<Address, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.cdp_manager_contract,
+                &self.cdp_manager_address,
+                "owns",
+                &[Token::Uint(vault_id)],
+            )
+            .await?;
+
+        let owner = tokens[0].clone().to_address();
+        Ok(owner.unwrap())
+    }
+
+    pub async fn get_cdp_can(
+        &self,
+        owner: &Address,
+        vault_id: Uint,
+        usr: &Address,
+    ) -> Result<bool, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.cdp_manager_contract,
+                &self.cdp_manager_address,
+                "cdpCan",
+                &[
+                    Token::Address(*owner),
+                    Token::Uint(vault_id),
+                    Token::Address(*usr),
+                ],
+            )
+            .await?;
+
+        let can = tokens[0].clone().to_uint();
+        Ok(can.unwrap() == Uint::one())
+    }
+
+    pub async fn get_urn_can(
+        &self,
+        owner: &Address,
+        usr: &Address,
+    ) -> Result<bool, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.cdp_manager_contract,
+                &self.cdp_manager_address,
+                "urnCan",
+                &[Token::Address(*owner), Token::Address(*usr)],
+            )
+            .await?;
+
+        let can = tokens[0].clone().to_uint();
+        Ok(can.unwrap() == Uint::one())
+    }
+
+    /// Every address the current owner allowed on the vault with `cdpAllow` and did not revoke.
+    pub async fn get_cdp_allowed(&self, vault_id: Uint) -> Result<Vec<Address>, Box<dyn Error>> {
+        let owner = self.get_owner(vault_id).await?;
+        let mut vault_topic = [0u8; 32];
+        vault_id.to_big_endian(&mut vault_topic);
+        let logs = self
+            .get_log_notes("cdpAllow", None, Some(Hash::from(vault_topic)))
+            .await?;
+        let mut allowed = Vec::new();
+        // arg2 is the allowed address
+        for usr in get_topic_addresses(&logs, 3) {
+            if self.get_cdp_can(&owner, vault_id, &usr).await? {
+                allowed.push(usr);
+            }
+        }
+        Ok(allowed)
+    }
+
+    /// Every address `owner` allowed on its urns with `urnAllow` and did not revoke.
+    pub async fn get_urn_allowed(&self, owner: &Address) -> Result<Vec<Address>, Box<dyn Error>> {
+        let logs = self
+            .get_log_notes("urnAllow", Some(Hash::from(*owner)), None)
+            .await?;
+        let mut allowed = Vec::new();
+        // arg1 is the allowed address
+        for usr in get_topic_addresses(&logs, 2) {
+            if self.get_urn_can(owner, &usr).await? {
+                allowed.push(usr);
+            }
+        }
+        Ok(allowed)
+    }
+
+    /// Reads the `LogNote` events emitted by calls to `function`.
+    async fn get_log_notes(
+        &self,
+        function: &str,
+        sender: Option<Hash>,
+        arg1: Option<Hash>,
+    ) -> Result<Vec<Log>, Box<dyn Error>> {
+        let function = self.cdp_manager_contract.function(function)?;
+        let mut sig = [0u8; 32];
+        sig[..4].copy_from_slice(&get_selector(function));
+        let filter = FilterBuilder::default()
+            .address(vec![self.cdp_manager_address])
+            .topics(
+                Some(vec![Hash::from(sig)]),
+                sender.map(|sender| vec![sender]),
+                arg1.map(|arg1| vec![arg1]),
+                None,
+            );
+        self.blockchain_reader
+            .get_logs_by_range(&filter, MCD_DEPLOYMENT_BLOCK, BlockNumber::Latest)
+            .await
+    }
+}
+
+fn get_topic_addresses(logs: &[Log], index: usize) -> Vec<Address> {
+    let mut addresses: Vec<Address> = Vec::new();
+    for log in logs {
+        if let Some(topic) = log.topics.get(index) {
+            let address = Address::from(*topic);
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+    addresses
 }
@@ -0,0 +1,316 @@
+use super::blockchain::{is_call_failure, BlockchainReader};
+use super::cdp_manager::MCD_DEPLOYMENT_BLOCK;
+use super::math::{Rad, Ray, Wad};
+use ethabi::{decode, Address, Contract, Hash, ParamType, Uint};
+use std::error::Error;
+use std::fmt;
+use web3::types::{BlockNumber, FilterBuilder};
+
+/// How the price of a collateral auction decreases, as computed by the Clipper `calc`.
+#[derive(Debug, Clone)]
+pub enum PriceCurve {
+    /// Reaches zero after `tau` seconds.
+    Linear { tau: u64 },
+    /// Multiplied by `cut` every second.
+    Exponential { cut: Ray },
+    /// Multiplied by `cut` every `step` seconds.
+    StairstepExponential { cut: Ray, step: u64 },
+}
+
+impl PriceCurve {
+    /// The auction price `duration` seconds after it started at `top`.
+    pub fn get_price(&self, top: Wad, duration: u64) -> Result<Wad, Box<dyn Error>> {
+        match self {
+            PriceCurve::Linear { tau } => match duration < *tau {
+                true => Ok(Wad(top.0 * Uint::from(tau - duration) / Uint::from(*tau))),
+                false => Ok(Wad::zero()),
+            },
+            PriceCurve::Exponential { cut } => top.checked_mul_ray(cut.checked_pow(duration)?),
+            PriceCurve::StairstepExponential { cut, step } => {
+                top.checked_mul_ray(cut.checked_pow(duration / step.max(&1))?)
+            }
+        }
+    }
+}
+
+impl fmt::Display for PriceCurve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let curve = match self {
+            PriceCurve::Linear { tau } => format!("linear to zero in {}s", tau),
+            PriceCurve::Exponential { cut } => format!("x{:.6} every second", cut),
+            PriceCurve::StairstepExponential { cut, step } => {
+                format!("x{:.4} every {}s", cut, step)
+            }
+        };
+        f.pad(&curve)
+    }
+}
+
+/// The parameters of the collateral auctions of an ilk.
+#[derive(Debug, Clone)]
+pub struct AuctionTerms {
+    /// The auction starts at the oracle price times `buf`.
+    pub buf: Ray,
+    /// Past `tail` seconds, or below `cusp` times its start, the auction has to be reset.
+    pub tail: u64,
+    pub cusp: Ray,
+    pub curve: PriceCurve,
+}
+
+impl AuctionTerms {
+    /// The price the collateral is sold at, when the keepers take as soon as the auction
+    /// goes below `market_price`. An auction started at `oracle_price` that needs a reset
+    /// restarts at the market price, the oracle having caught up by then.
+    pub fn get_clearing_price(
+        &self,
+        oracle_price: Wad,
+        market_price: Wad,
+    ) -> Result<Wad, Box<dyn Error>> {
+        match self.get_clearing_price_from(oracle_price, market_price)? {
+            Some(price) => Ok(price),
+            None => match self.get_clearing_price_from(market_price, market_price)? {
+                Some(price) => Ok(price),
+                None => Err(Box::new(ClipperError(format!(
+                    "the auction never goes below the market price ({:.2}) before a reset",
+                    market_price
+                )))),
+            },
+        }
+    }
+
+    fn get_clearing_price_from(
+        &self,
+        oracle_price: Wad,
+        market_price: Wad,
+    ) -> Result<Option<Wad>, Box<dyn Error>> {
+        let top = oracle_price.checked_mul_ray(self.buf)?;
+        if self.curve.get_price(top, self.tail)? > market_price {
+            return Ok(None);
+        }
+        // the curve only decreases, the first second below the market price is searched
+        let (mut low, mut high) = (0, self.tail);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.curve.get_price(top, middle)? > market_price {
+                true => low = middle + 1,
+                false => high = middle,
+            }
+        }
+        let price = self.curve.get_price(top, low)?;
+        match price < top.checked_mul_ray(self.cusp)? {
+            true => Ok(None),
+            false => Ok(Some(price)),
+        }
+    }
+}
+
+/// A purchase of collateral in an auction.
+#[derive(Debug, Clone)]
+pub struct Take {
+    pub id: Uint,
+    pub block_number: u64,
+    /// DAI per unit of collateral.
+    pub price: Ray,
+    /// DAI paid by the keeper.
+    pub owe: Rad,
+    /// DAI still to raise after this purchase.
+    pub tab: Rad,
+    /// Collateral left in the auction after this purchase, returned to the vault once the
+    /// tab is covered.
+    pub lot: Wad,
+}
+
+pub struct Clipper<'a> {
+    blockchain_reader: &'a dyn BlockchainReader,
+    clipper_address: Address,
+    clipper_contract: Contract,
+    abacus_contract: Contract,
+}
+
+impl<'a> Clipper<'a> {
+    pub fn new(
+        blockchain_reader: &'a (dyn BlockchainReader + 'a),
+        clipper_address: Address,
+    ) -> Result<Self, Box<dyn Error>> {
+        let clipper_abi: &[u8] = include_bytes!("abi/clipper.abi");
+        let clipper_contract = Contract::load(clipper_abi)?;
+        let abacus_abi: &[u8] = include_bytes!("abi/abacus.abi");
+        let abacus_contract = Contract::load(abacus_abi)?;
+        Ok(Self {
+            blockchain_reader,
+            clipper_address,
+            clipper_contract,
+            abacus_contract,
+        })
+    }
+
+    async fn get_uint(&self, name: &str) -> Result<Uint, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(&self.clipper_contract, &self.clipper_address, name, &[])
+            .await?;
+
+        let value = tokens[0].clone().to_uint();
+        Ok(value.unwrap())
+    }
+
+    pub async fn get_auction_terms(&self) -> Result<AuctionTerms, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(&self.clipper_contract, &self.clipper_address, "calc", &[])
+            .await?;
+        let calc_address = tokens[0].clone().to_address().unwrap();
+
+        Ok(AuctionTerms {
+            buf: Ray(self.get_uint("buf").await?),
+            tail: self.get_uint("tail").await?.as_u64(),
+            cusp: Ray(self.get_uint("cusp").await?),
+            curve: self.get_price_curve(&calc_address).await?,
+        })
+    }
+
+    /// Each kind of calc has its own parameters, the ones it answers to tell which it is.
+    async fn get_price_curve(&self, calc_address: &Address) -> Result<PriceCurve, Box<dyn Error>> {
+        let read = |name: &'static str| {
+            self.blockchain_reader
+                .call_function(&self.abacus_contract, calc_address, name, &[])
+        };
+        match read("tau").await {
+            Ok(tokens) => {
+                let tau = tokens[0].clone().to_uint().unwrap();
+                return Ok(PriceCurve::Linear { tau: tau.as_u64() });
+            }
+            Err(e) if is_call_failure(&*e) => {}
+            Err(e) => return Err(e),
+        }
+        let cut = Ray(read("cut").await?[0].clone().to_uint().unwrap());
+        match read("step").await {
+            Ok(tokens) => {
+                let step = tokens[0].clone().to_uint().unwrap();
+                Ok(PriceCurve::StairstepExponential {
+                    cut,
+                    step: step.as_u64(),
+                })
+            }
+            Err(e) if is_call_failure(&*e) => Ok(PriceCurve::Exponential { cut }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Every purchase in the auction `id`, oldest first.
+    pub async fn get_takes(&self, id: Uint) -> Result<Vec<Take>, Box<dyn Error>> {
+        let take = self.clipper_contract.event("Take")?.signature();
+        let mut id_topic = [0u8; 32];
+        id.to_big_endian(&mut id_topic);
+        let filter = FilterBuilder::default()
+            .address(vec![self.clipper_address])
+            .topics(
+                Some(vec![take]),
+                Some(vec![Hash::from(id_topic)]),
+                None,
+                None,
+            );
+        let logs = self
+            .blockchain_reader
+            .get_logs_by_range(&filter, MCD_DEPLOYMENT_BLOCK, BlockNumber::Latest)
+            .await?;
+
+        let mut takes = Vec::with_capacity(logs.len());
+        for log in logs {
+            let tokens = decode(
+                &[
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                ],
+                &log.data.0,
+            )?;
+            takes.push(Take {
+                id,
+                block_number: log.block_number.unwrap_or_default().as_u64(),
+                price: Ray(tokens[1].clone().to_uint().unwrap()),
+                owe: Rad(tokens[2].clone().to_uint().unwrap()),
+                tab: Rad(tokens[3].clone().to_uint().unwrap()),
+                lot: Wad(tokens[4].clone().to_uint().unwrap()),
+            });
+        }
+        Ok(takes)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClipperError(pub String);
+
+impl fmt::Display for ClipperError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ClipperError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(value: f64) -> Ray {
+        Ray(Wad::from_f64(value).unwrap().0 * Uint::exp10(9))
+    }
+
+    #[test]
+    fn linear_curve_reaches_zero_at_tau() {
+        let curve = PriceCurve::Linear { tau: 3600 };
+        let top = Wad::from_f64(1000.0).unwrap();
+        assert_eq!(curve.get_price(top, 0).unwrap(), top);
+        assert_eq!(
+            curve.get_price(top, 900).unwrap(),
+            Wad::from_f64(750.0).unwrap()
+        );
+        assert_eq!(curve.get_price(top, 3600).unwrap(), Wad::zero());
+        assert_eq!(curve.get_price(top, 7200).unwrap(), Wad::zero());
+    }
+
+    #[test]
+    fn exponential_curve_cuts_every_second() {
+        let curve = PriceCurve::Exponential { cut: ray(0.9) };
+        let top = Wad::from_f64(1000.0).unwrap();
+        assert_eq!(curve.get_price(top, 0).unwrap(), top);
+        assert_eq!(
+            curve.get_price(top, 2).unwrap(),
+            Wad::from_f64(810.0).unwrap()
+        );
+    }
+
+    #[test]
+    fn stairstep_curve_cuts_every_step() {
+        let curve = PriceCurve::StairstepExponential {
+            cut: ray(0.99),
+            step: 90,
+        };
+        let top = Wad::from_f64(1000.0).unwrap();
+        assert_eq!(curve.get_price(top, 89).unwrap(), top);
+        assert_eq!(
+            curve.get_price(top, 179).unwrap(),
+            Wad::from_f64(990.0).unwrap()
+        );
+        assert_eq!(
+            curve.get_price(top, 180).unwrap(),
+            Wad::from_f64(980.1).unwrap()
+        );
+        // a zero step is taken as one second
+        let curve = PriceCurve::StairstepExponential {
+            cut: ray(0.5),
+            step: 0,
+        };
+        assert_eq!(
+            curve.get_price(top, 2).unwrap(),
+            Wad::from_f64(250.0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use super::blockchain::BlockchainReader;
+use super::cdp_manager::{CdpManager, MCD_DEPLOYMENT_BLOCK};
+use super::clipper::{Clipper, Take};
+use super::math::{Rad, Wad};
+use super::median::Median;
+use super::saver::Saver;
+use super::spot::Spot;
+use super::vault::{Liquidation, LiquidationTerms};
+use ethabi::{decode, Address, Contract, Hash, ParamType, Token, Uint};
+use std::error::Error;
+use web3::types::{BlockNumber, FilterBuilder};
+
+const DOG_ADDRESS: &str = "135954d155898d42c90d2a57824c690e0c7bef1b";
+
+/// The liquidation parameters of an ilk.
+#[derive(Debug, Clone)]
+pub struct DogIlk {
+    /// The Clipper auctioning the collateral.
+    pub clip: Address,
+    /// The penalty, the debt is repaid times `chop`.
+    pub chop: Wad,
+    /// The most DAI that can be auctioned at once, and the amount being auctioned.
+    pub hole: Rad,
+    pub dirt: Rad,
+}
+
+/// A vault sent to auction.
+#[derive(Debug, Clone)]
+pub struct Bark {
+    pub block_number: u64,
+    pub timestamp: u64,
+    pub transaction_hash: Hash,
+    /// The collateral seized.
+    pub ink: Wad,
+    pub art: Wad,
+    /// The debt, before the penalty.
+    pub due: Rad,
+    pub clip: Address,
+    /// The auction id in the Clipper.
+    pub id: Uint,
+}
+
+pub struct Dog<'a> {
+    blockchain_reader: &'a dyn BlockchainReader,
+    dog_address: Address,
+    dog_contract: Contract,
+}
+
+impl<'a> Dog<'a> {
+    pub fn new(blockchain_reader: &'a (dyn BlockchainReader + 'a)) -> Result<Self, Box<dyn Error>> {
+        let dog_address: Address = DOG_ADDRESS.parse()?;
+        let dog_abi: &[u8] = include_bytes!("abi/dog.abi");
+        let dog_contract = Contract::load(dog_abi)?;
+        Ok(Self {
+            blockchain_reader,
+            dog_address,
+            dog_contract,
+        })
+    }
+
+    pub async fn get_ilk(&self, ilk_id: &[u8]) -> Result<DogIlk, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.dog_contract,
+                &self.dog_address,
+                "ilks",
+                &[Token::FixedBytes(ilk_id.to_vec())],
+            )
+            .await?;
+
+        Ok(DogIlk {
+            clip: tokens[0].clone().to_address().unwrap(),
+            chop: Wad(tokens[1].clone().to_uint().unwrap()),
+            hole: Rad(tokens[2].clone().to_uint().unwrap()),
+            dirt: Rad(tokens[3].clone().to_uint().unwrap()),
+        })
+    }
+
+    /// The liquidation ratio, the penalty and the auction parameters of the ilk.
+    pub async fn get_liquidation_terms(
+        &self,
+        ilk_id: &[u8],
+    ) -> Result<LiquidationTerms, Box<dyn Error>> {
+        let ilk = self.get_ilk(ilk_id).await?;
+        let spot = Spot::new(self.blockchain_reader)?;
+        let clipper = Clipper::new(self.blockchain_reader, ilk.clip)?;
+        Ok(LiquidationTerms {
+            ratio: spot.get_liquidation_ratio(ilk_id).await?.to_wad(),
+            penalty: ilk.chop,
+            auction: Some(clipper.get_auction_terms().await?),
+        })
+    }
+
+    /// Every liquidation of the urn by the Dog, oldest first. The liquidations before the Dog
+    /// went live, in April 2021, went through the Cat and its Flippers and are not read.
+    pub async fn get_barks(&self, urn: &Address) -> Result<Vec<Bark>, Box<dyn Error>> {
+        let bark = self.dog_contract.event("Bark")?.signature();
+        let filter = FilterBuilder::default()
+            .address(vec![self.dog_address])
+            .topics(Some(vec![bark]), None, Some(vec![Hash::from(*urn)]), None);
+        let logs = self
+            .blockchain_reader
+            .get_logs_by_range(&filter, MCD_DEPLOYMENT_BLOCK, BlockNumber::Latest)
+            .await?;
+
+        let mut barks = Vec::with_capacity(logs.len());
+        for log in logs {
+            let tokens = decode(
+                &[
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                    ParamType::Address,
+                ],
+                &log.data.0,
+            )?;
+            let block_number = log.block_number.unwrap_or_default().as_u64();
+            barks.push(Bark {
+                block_number,
+                timestamp: self
+                    .blockchain_reader
+                    .get_block_timestamp(block_number)
+                    .await?,
+                transaction_hash: log.transaction_hash.unwrap_or_default(),
+                ink: Wad(tokens[0].clone().to_uint().unwrap()),
+                art: Wad(tokens[1].clone().to_uint().unwrap()),
+                due: Rad(tokens[2].clone().to_uint().unwrap()),
+                clip: tokens[3].clone().to_address().unwrap(),
+                id: Uint::from_big_endian(log.topics[3].as_bytes()),
+            });
+        }
+        Ok(barks)
+    }
+}
+
+/// A liquidation of the vault as it happened, next to what `Vault::liquidate` estimates.
+#[derive(Debug, Clone)]
+pub struct LiquidationReport {
+    pub bark: Bark,
+    /// The oracle price the auction started at.
+    pub oracle_price: Wad,
+    /// The next oracle price, the closest to the market.
+    pub market_price: Wad,
+    /// With today's liquidation terms of the ilk.
+    pub estimated: Liquidation,
+    pub takes: Vec<Take>,
+}
+
+impl LiquidationReport {
+    /// The collateral given back to the vault, `None` while the auction is not over.
+    pub fn get_collateral_returned(&self) -> Option<Wad> {
+        match self.takes.last() {
+            Some(take) if take.tab.is_zero() => Some(take.lot),
+            _ => None,
+        }
+    }
+
+    pub fn get_collateral_sold(&self) -> Option<Wad> {
+        self.get_collateral_returned()
+            .map(|returned| self.bark.ink.saturating_sub(returned))
+    }
+}
+
+/// Reads every liquidation of the vault and the auction purchases that followed, the Cat
+/// liquidations from before April 2021 excepted. This needs an archive node.
+pub async fn get_liquidation_reports(
+    blockchain_reader: &dyn BlockchainReader,
+    vault_id: Uint,
+) -> Result<Vec<LiquidationReport>, Box<dyn Error>> {
+    let dog = Dog::new(blockchain_reader)?;
+    let saver = Saver::new(blockchain_reader)?;
+    let cdp_manager = CdpManager::new(blockchain_reader)?;
+    let spot = Spot::new(blockchain_reader)?;
+    let ilk_id = cdp_manager.get_ilk_id(vault_id).await?;
+    let terms = dog.get_liquidation_terms(&ilk_id).await?;
+    let median = Median::new(blockchain_reader, spot.get_median_address(&ilk_id).await?)?;
+    let urn = cdp_manager.get_urn(vault_id).await?;
+
+    let mut reports = Vec::new();
+    for bark in dog.get_barks(&urn).await? {
+        let block = BlockNumber::Number(bark.block_number.into());
+        let oracle_price = median.get_price_at(false, block).await?;
+        let market_price = median.get_price_at(true, block).await?;
+        let mut vault = saver.get_vault_at(vault_id, block).await?;
+        vault.collateral = bark.ink;
+        vault.debt = bark.due.to_wad();
+        let (_, estimated) = vault.liquidate(oracle_price, market_price, &terms)?;
+        let clipper = Clipper::new(blockchain_reader, bark.clip)?;
+        let takes = clipper.get_takes(bark.id).await?;
+        reports.push(LiquidationReport {
+            bark,
+            oracle_price,
+            market_price,
+            estimated,
+            takes,
+        });
+    }
+    Ok(reports)
+}
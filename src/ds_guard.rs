@@ -0,0 +1,79 @@
+use super::blockchain::BlockchainReader;
+use super::cdp_manager::MCD_DEPLOYMENT_BLOCK;
+use ethabi::{Address, Contract, Hash, Token};
+use std::error::Error;
+use web3::types::{BlockNumber, FilterBuilder};
+
+pub struct DsGuard<'a> {
+    blockchain_reader: &'a dyn BlockchainReader,
+    ds_guard_address: Address,
+    ds_guard_contract: Contract,
+}
+
+impl<'a> DsGuard<'a> {
+    pub fn new(
+        blockchain_reader: &'a (dyn BlockchainReader + 'a),
+        ds_guard_address: Address,
+    ) -> Result<Self, Box<dyn Error>> {
+        let ds_guard_abi: &[u8] = include_bytes!("abi/ds_guard.abi");
+        let ds_guard_contract = Contract::load(ds_guard_abi)?;
+        Ok(Self {
+            blockchain_reader,
+            ds_guard_address,
+            ds_guard_contract,
+        })
+    }
+
+    pub async fn can_call(
+        &self,
+        src: &Address,
+        dst: &Address,
+        sig: [u8; 4],
+    ) -> Result<bool, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.ds_guard_contract,
+                &self.ds_guard_address,
+                "canCall",
+                &[
+                    Token::Address(*src),
+                    Token::Address(*dst),
+                    Token::FixedBytes(sig.to_vec()),
+                ],
+            )
+            .await?;
+
+        let can_call = tokens[0].clone().to_bool();
+        Ok(can_call.unwrap())
+    }
+
+    /// Every address still permitted to call `sig` on `dst`, found from the `LogPermit` events.
+    pub async fn get_permitted(
+        &self,
+        dst: &Address,
+        sig: [u8; 4],
+    ) -> Result<Vec<Address>, Box<dyn Error>> {
+        let event = self.ds_guard_contract.event("LogPermit")?;
+        let filter = FilterBuilder::default()
+            .address(vec![self.ds_guard_address])
+            .topics(
+                Some(vec![event.signature()]),
+                None,
+                Some(vec![Hash::from(*dst)]),
+                None,
+            );
+        let logs = self
+            .blockchain_reader
+            .get_logs_by_range(&filter, MCD_DEPLOYMENT_BLOCK, BlockNumber::Latest)
+            .await?;
+        let mut permitted: Vec<Address> = Vec::new();
+        for log in logs {
+            let src = Address::from(log.topics[1]);
+            if !permitted.contains(&src) && self.can_call(&src, dst, sig).await? {
+                permitted.push(src);
+            }
+        }
+        Ok(permitted)
+    }
+}
@@ -0,0 +1,61 @@
+use super::blockchain::{get_selector, BlockchainReader};
+use ethabi::{Address, Contract};
+use std::error::Error;
+
+pub struct DsProxy<'a> {
+    blockchain_reader: &'a dyn BlockchainReader,
+    ds_proxy_address: Address,
+    ds_proxy_contract: Contract,
+}
+
+impl<'a> DsProxy<'a> {
+    pub fn new(
+        blockchain_reader: &'a (dyn BlockchainReader + 'a),
+        ds_proxy_address: Address,
+    ) -> Result<Self, Box<dyn Error>> {
+        let ds_proxy_abi: &[u8] = include_bytes!("abi/ds_proxy.abi");
+        let ds_proxy_contract = Contract::load(ds_proxy_abi)?;
+        Ok(Self {
+            blockchain_reader,
+            ds_proxy_address,
+            ds_proxy_contract,
+        })
+    }
+
+    /// The selector of `execute(address,bytes)`, the call a DSGuard has to permit.
+    pub fn get_execute_selector(&self) -> Result<[u8; 4], Box<dyn Error>> {
+        let execute = self.ds_proxy_contract.function("execute")?;
+        Ok(get_selector(execute))
+    }
+
+    pub async fn get_owner(&self) -> Result<Address, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.ds_proxy_contract,
+                &self.ds_proxy_address,
+                "owner",
+                &[],
+            )
+            .await?;
+
+        let owner = tokens[0].clone().to_address();
+        Ok(owner.unwrap())
+    }
+
+    /// The `DSAuthority` deciding who else may call `execute`, zero when there is none.
+    pub async fn get_authority(&self) -> Result<Address, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.ds_proxy_contract,
+                &self.ds_proxy_address,
+                "authority",
+                &[],
+            )
+            .await?;
+
+        let authority = tokens[0].clone().to_address();
+        Ok(authority.unwrap())
+    }
+}
@@ -1,4 +1,10 @@
-use ethabi::Address;
+use super::blockchain::{is_decode_error, BlockchainReader};
+use ethabi::{Address, Contract, Token, Uint};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Mutex;
+
+static TOKENS: Mutex<BTreeMap<Address, Erc20Token>> = Mutex::new(BTreeMap::new());
 
 #[derive(Debug, Clone)]
 pub struct Erc20Token {
@@ -7,3 +13,85 @@ pub struct Erc20Token {
     pub decimals: usize,
     pub address: Address,
 }
+
+impl Erc20Token {
+    /// Reads the token metadata from chain, once per address.
+    pub async fn load(
+        blockchain_reader: &dyn BlockchainReader,
+        address: Address,
+    ) -> Result<Erc20Token, Box<dyn Error>> {
+        if let Some(token) = TOKENS.lock().unwrap().get(&address) {
+            return Ok(token.clone());
+        }
+
+        let erc20_abi: &[u8] = include_bytes!("abi/erc20.abi");
+        let erc20_contract = Contract::load(erc20_abi)?;
+        let (name, symbol) = match get_strings(blockchain_reader, &erc20_contract, &address).await {
+            Ok(strings) => strings,
+            // MKR style tokens return bytes32 instead of string
+            Err(e) if is_decode_error(&*e) => {
+                let erc20_bytes32_abi: &[u8] = include_bytes!("abi/erc20_bytes32.abi");
+                let erc20_bytes32_contract = Contract::load(erc20_bytes32_abi)?;
+                get_strings(blockchain_reader, &erc20_bytes32_contract, &address).await?
+            }
+            Err(e) => return Err(e),
+        };
+        let tokens = blockchain_reader
+            .call_function(&erc20_contract, &address, "decimals", &[])
+            .await?;
+        let decimals = tokens[0].clone().to_uint().unwrap().as_usize();
+
+        let token = Erc20Token {
+            name,
+            symbol,
+            decimals,
+            address,
+        };
+        TOKENS.lock().unwrap().insert(address, token.clone());
+        Ok(token)
+    }
+
+    pub async fn get_balance(
+        &self,
+        blockchain_reader: &dyn BlockchainReader,
+        owner: &Address,
+    ) -> Result<Uint, Box<dyn Error>> {
+        let erc20_abi: &[u8] = include_bytes!("abi/erc20.abi");
+        let erc20_contract = Contract::load(erc20_abi)?;
+        let tokens = blockchain_reader
+            .call_function(
+                &erc20_contract,
+                &self.address,
+                "balanceOf",
+                &[Token::Address(*owner)],
+            )
+            .await?;
+        let balance = tokens[0].clone().to_uint();
+        Ok(balance.unwrap())
+    }
+}
+
+async fn get_strings(
+    blockchain_reader: &dyn BlockchainReader,
+    contract: &Contract,
+    address: &Address,
+) -> Result<(String, String), Box<dyn Error>> {
+    let name = blockchain_reader
+        .call_function(contract, address, "name", &[])
+        .await?;
+    let symbol = blockchain_reader
+        .call_function(contract, address, "symbol", &[])
+        .await?;
+    Ok((to_string(&name[0]), to_string(&symbol[0])))
+}
+
+fn to_string(token: &Token) -> String {
+    match token {
+        Token::String(s) => s.clone(),
+        Token::FixedBytes(bytes) => {
+            let bytes: Vec<u8> = bytes.iter().cloned().take_while(|b| *b != 0).collect();
+            String::from_utf8_lossy(&bytes).to_string()
+        }
+        _ => String::new(),
+    }
+}
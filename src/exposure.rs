@@ -0,0 +1,220 @@
+use super::blockchain::BlockchainReader;
+use super::erc_20::Erc20Token;
+use super::math::Wad;
+use super::vault::{Equity, Vault, VaultError};
+use ethabi::{Address, Uint};
+use std::error::Error;
+
+const WETH_ADDRESS: &str = "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+const DAI_ADDRESS: &str = "6b175474e89094c44da98b954eedeac495271d0f";
+
+#[derive(Debug, Clone)]
+pub struct Wallet {
+    pub address: Address,
+    pub eth: Uint,
+    pub weth: Uint,
+    pub dai: Uint,
+    /// Always zero when the collateral is WETH, the balance is in `weth`.
+    pub collateral: Uint,
+}
+
+/// The vault and the wallets around it, seen as one position.
+#[derive(Debug, Clone)]
+pub struct Exposure {
+    pub vault: Vault,
+    pub wallets: Vec<Wallet>,
+}
+
+impl Exposure {
+    pub async fn load(
+        blockchain_reader: &dyn BlockchainReader,
+        vault: Vault,
+        addresses: &[Address],
+    ) -> Result<Self, Box<dyn Error>> {
+        let weth = Erc20Token::load(blockchain_reader, WETH_ADDRESS.parse()?).await?;
+        let dai = Erc20Token::load(blockchain_reader, DAI_ADDRESS.parse()?).await?;
+        let mut wallets = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            let mut collateral = Uint::zero();
+            if vault.token.address != weth.address {
+                collateral = vault.token.get_balance(blockchain_reader, address).await?;
+            }
+            wallets.push(Wallet {
+                address: *address,
+                eth: blockchain_reader.get_balance(address).await?,
+                weth: weth.get_balance(blockchain_reader, address).await?,
+                dai: dai.get_balance(blockchain_reader, address).await?,
+                collateral,
+            });
+        }
+        Ok(Self { vault, wallets })
+    }
+
+    /// DAI value of everything that is not DAI: the locked collateral and the wallets' tokens.
+    pub fn get_risky_value(&self, price: Wad, eth_price: Wad) -> Result<Wad, Box<dyn Error>> {
+        let mut value = self.vault.collateral.checked_mul(price)?;
+        for wallet in &self.wallets {
+            value = value.checked_add(self.get_wallet_risky_value(wallet, price, eth_price)?)?;
+        }
+        Ok(value)
+    }
+
+    /// The net value of the vault and the wallets, the wallets can cover an underwater vault.
+    pub fn get_equity(&self, price: Wad, eth_price: Wad) -> Result<Equity, Box<dyn Error>> {
+        let mut assets = Wad::zero();
+        for wallet in &self.wallets {
+            assets = assets
+                .checked_add(Wad(wallet.dai))?
+                .checked_add(self.get_wallet_risky_value(wallet, price, eth_price)?)?;
+        }
+        assets = assets.checked_add(self.vault.collateral.checked_mul(price)?)?;
+        Equity::new(assets, self.vault.debt)
+    }
+
+    pub fn get_dai_value(&self, price: Wad, eth_price: Wad) -> Result<Wad, Box<dyn Error>> {
+        match self.get_equity(price, eth_price)? {
+            Equity::Positive(value) => Ok(value),
+            Equity::Underwater(deficit) => Err(Box::new(VaultError(format!(
+                "the position is underwater by {:.2} dai",
+                deficit
+            )))),
+        }
+    }
+
+    pub fn get_col_value(&self, price: Wad, eth_price: Wad) -> Result<Wad, Box<dyn Error>> {
+        self.get_dai_value(price, eth_price)?.checked_div(price)
+    }
+
+    /// How much the net worth moves when every non DAI asset moves by 1%.
+    pub fn get_leverage(&self, price: Wad, eth_price: Wad) -> Result<f64, Box<dyn Error>> {
+        let risky_value = self.get_risky_value(price, eth_price)?;
+        let dai_value = self.get_dai_value(price, eth_price)?;
+        Ok(risky_value.checked_div(dai_value)?.to_f64())
+    }
+
+    fn get_wallet_risky_value(
+        &self,
+        wallet: &Wallet,
+        price: Wad,
+        eth_price: Wad,
+    ) -> Result<Wad, Box<dyn Error>> {
+        let collateral = Wad::from_decimals(wallet.collateral, self.vault.token.decimals)?;
+        let eth = Wad(wallet.eth).checked_add(Wad(wallet.weth))?;
+        collateral
+            .checked_mul(price)?
+            .checked_add(eth.checked_mul(eth_price)?)
+    }
+
+    pub fn show(&self, price: Wad, eth_price: Wad) -> Result<(), Box<dyn Error>> {
+        let symbol = self.vault.token.symbol.to_lowercase();
+        println!("vault:");
+        match self.vault.get_equity(price)? {
+            Equity::Positive(value) => {
+                println!("{:>15.2} dai", value);
+                println!("{:>15.2} {}", value.checked_div(price)?, symbol);
+            }
+            Equity::Underwater(deficit) => println!("underwater by {:.2} dai", deficit),
+        }
+        for wallet in &self.wallets {
+            println!("{:#x}:", wallet.address);
+            println!("{:>15.2} eth", Wad(wallet.eth));
+            println!("{:>15.2} weth", Wad(wallet.weth));
+            println!("{:>15.2} dai", Wad(wallet.dai));
+            if symbol != "weth" {
+                println!(
+                    "{:>15.2} {}",
+                    Wad::from_decimals(wallet.collateral, self.vault.token.decimals)?,
+                    symbol
+                );
+            }
+        }
+        // the wallets may not cover the vault either
+        if let Equity::Underwater(deficit) = self.get_equity(price, eth_price)? {
+            println!("net value: underwater by {:.2} dai", deficit);
+            return Ok(());
+        }
+        let dai_value = self.get_dai_value(price, eth_price)?;
+        let col_value = self.get_col_value(price, eth_price)?;
+        println!("net value:");
+        println!("{:>15.2} dai", dai_value);
+        println!("{:>15.2} {}", col_value, symbol);
+        println!(
+            "{:<11}: x{:.2}",
+            "leverage",
+            self.get_leverage(price, eth_price)?
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::wad;
+    use crate::optimizer::get_default_vault;
+
+    fn wallet(eth: f64, weth: f64, dai: f64, collateral: Uint) -> Wallet {
+        Wallet {
+            address: Address::zero(),
+            eth: wad(eth).0,
+            weth: wad(weth).0,
+            dai: wad(dai).0,
+            collateral,
+        }
+    }
+
+    #[test]
+    fn weth_vault_counts_the_wallet_weth_once() {
+        let mut vault = get_default_vault().unwrap();
+        vault.token.address = WETH_ADDRESS.parse().unwrap();
+        vault.collateral = wad(10.0);
+        vault.debt = wad(1000.0);
+        let exposure = Exposure {
+            vault,
+            wallets: vec![wallet(1.0, 2.0, 500.0, Uint::zero())],
+        };
+        let price = wad(2000.0);
+        // 13 eth in all, 500 dai and 1000 of debt
+        assert_eq!(
+            exposure.get_risky_value(price, price).unwrap(),
+            wad(26000.0)
+        );
+        assert_eq!(
+            exposure.get_equity(price, price).unwrap(),
+            Equity::Positive(wad(25500.0))
+        );
+        let leverage = exposure.get_leverage(price, price).unwrap();
+        assert!((leverage - 26000.0 / 25500.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn other_vault_counts_the_wallet_collateral_and_eth_apart() {
+        let mut vault = get_default_vault().unwrap();
+        vault.token.decimals = 8;
+        vault.collateral = wad(1.0);
+        vault.debt = wad(10000.0);
+        // half a token with 8 decimals
+        let exposure = Exposure {
+            vault,
+            wallets: vec![wallet(1.0, 0.0, 0.0, Uint::from(50_000_000))],
+        };
+        let (price, eth_price) = (wad(30000.0), wad(2000.0));
+        assert_eq!(
+            exposure.get_risky_value(price, eth_price).unwrap(),
+            wad(47000.0)
+        );
+        assert_eq!(
+            exposure.get_equity(price, eth_price).unwrap(),
+            Equity::Positive(wad(37000.0))
+        );
+        let leverage = exposure.get_leverage(price, eth_price).unwrap();
+        assert!((leverage - 47000.0 / 37000.0).abs() < 1e-9);
+        // the wallets cannot cover the vault at 5000
+        let price = wad(5000.0);
+        assert_eq!(
+            exposure.get_equity(price, eth_price).unwrap(),
+            Equity::Underwater(wad(500.0))
+        );
+        assert!(exposure.get_leverage(price, eth_price).is_err());
+    }
+}
@@ -0,0 +1,27 @@
+use super::math::Wad;
+use super::optimizer::get_default_vault;
+use super::vault::{VatLimits, Vault};
+
+pub fn wad(value: f64) -> Wad {
+    Wad::from_f64(value).unwrap()
+}
+
+/// 180 collateral for 10000 debt, 180% at 100, repaid below 160% and boosted above 230%,
+/// back to 190% either way.
+pub fn vault() -> Vault {
+    let mut vault = get_default_vault().unwrap();
+    vault.min_ratio = Wad::from_percent(160);
+    vault.repay_ratio = Wad::from_percent(190);
+    vault.boost_ratio = Wad::from_percent(190);
+    vault.max_ratio = Wad::from_percent(230);
+    vault
+}
+
+/// A 150% liquidation ratio, a 5000 DAI dust and room for 100000 more DAI.
+pub fn limits() -> VatLimits {
+    VatLimits {
+        liquidation_ratio: Wad::from_percent(150),
+        dust: wad(5000.0),
+        available_debt: wad(100_000.0),
+    }
+}
@@ -0,0 +1,44 @@
+use super::blockchain::BlockchainReader;
+use ethabi::{Address, Contract};
+use std::error::Error;
+
+pub struct GemJoin<'a> {
+    blockchain_reader: &'a dyn BlockchainReader,
+    gem_join_address: Address,
+    gem_join_contract: Contract,
+}
+
+impl<'a> GemJoin<'a> {
+    pub fn new(
+        blockchain_reader: &'a (dyn BlockchainReader + 'a),
+        gem_join_address: Address,
+    ) -> Result<Self, Box<dyn Error>> {
+        let gem_join_abi: &[u8] = include_bytes!("abi/gem_join.abi");
+        let gem_join_contract = Contract::load(gem_join_abi)?;
+        Ok(Self {
+            blockchain_reader,
+            gem_join_address,
+            gem_join_contract,
+        })
+    }
+
+    pub async fn get_gem_address(&self) -> Result<Address, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(&self.gem_join_contract, &self.gem_join_address, "gem", &[])
+            .await?;
+
+        let gem_address = tokens[0].clone().to_address();
+        Ok(gem_address.unwrap())
+    }
+
+    pub async fn get_decimals(&self) -> Result<usize, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(&self.gem_join_contract, &self.gem_join_address, "dec", &[])
+            .await?;
+
+        let decimals = tokens[0].clone().to_uint();
+        Ok(decimals.unwrap().as_usize())
+    }
+}
@@ -0,0 +1,53 @@
+use super::blockchain::BlockchainReader;
+use ethabi::{Address, Contract, Token};
+use std::error::Error;
+
+const ILK_REGISTRY_ADDRESS: &str = "5a464c28d19848f44199d003bef5ecc87d090f87";
+
+pub struct IlkRegistry<'a> {
+    blockchain_reader: &'a dyn BlockchainReader,
+    ilk_registry_address: Address,
+    ilk_registry_contract: Contract,
+}
+impl<'a> IlkRegistry<'a> {
+    pub fn new(blockchain_reader: &'a (dyn BlockchainReader + 'a)) -> Result<Self, Box<dyn Error>> {
+        let ilk_registry_address: Address = ILK_REGISTRY_ADDRESS.parse()?;
+        let ilk_registry_abi: &[u8] = include_bytes!("abi/ilk_registry.abi");
+        let ilk_registry_contract = Contract::load(ilk_registry_abi)?;
+        Ok(Self {
+            blockchain_reader,
+            ilk_registry_address,
+            ilk_registry_contract,
+        })
+    }
+
+    pub async fn get_gem_address(&self, ilk_id: &[u8]) -> Result<Address, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.ilk_registry_contract,
+                &self.ilk_registry_address,
+                "gem",
+                &[Token::FixedBytes(ilk_id.to_vec())],
+            )
+            .await?;
+
+        let gem_address = tokens[0].clone().to_address();
+        Ok(gem_address.unwrap())
+    }
+
+    pub async fn get_join_address(&self, ilk_id: &[u8]) -> Result<Address, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.ilk_registry_contract,
+                &self.ilk_registry_address,
+                "join",
+                &[Token::FixedBytes(ilk_id.to_vec())],
+            )
+            .await?;
+
+        let join_address = tokens[0].clone().to_address();
+        Ok(join_address.unwrap())
+    }
+}
@@ -1,20 +1,69 @@
 mod blockchain;
 mod cdp_manager;
+mod clipper;
+mod dog;
+mod ds_guard;
+mod ds_proxy;
 mod erc_20;
+mod exposure;
+#[cfg(test)]
+mod fixtures;
+mod gem_join;
+mod ilk_registry;
+mod logger;
+mod math;
 mod median;
 mod optimizer;
+mod permissions;
+mod planner;
+mod proxy_registry;
 mod saver;
+mod simulation;
 mod spot;
+mod subscribers;
+mod uniswapv2_factory;
 mod uniswapv2_pair;
+mod vat;
 mod vault;
 
 pub use crate::blockchain::BlockchainReader;
 pub use crate::blockchain::HttpBlockchainReader;
-pub use crate::cdp_manager::CdpManager;
+pub use crate::cdp_manager::{CdpManager, MCD_DEPLOYMENT_BLOCK};
+pub use crate::clipper::{AuctionTerms, Clipper, ClipperError, PriceCurve, Take};
+pub use crate::dog::{get_liquidation_reports, Bark, Dog, DogIlk, LiquidationReport};
+pub use crate::ds_guard::DsGuard;
+pub use crate::ds_proxy::DsProxy;
 pub use crate::erc_20::Erc20Token;
+pub use crate::exposure::{Exposure, Wallet};
+pub use crate::gem_join::GemJoin;
+pub use crate::ilk_registry::IlkRegistry;
+pub use crate::logger::{
+    get_execution_reports, Calibration, CalibrationError, Execution, ExecutionReport, Logger,
+};
+pub use crate::math::{MathError, Rad, Ray, Wad};
 pub use crate::median::Median;
-pub use crate::optimizer::{get_simulation, Scenario, ScenarioResult};
-pub use crate::saver::Saver;
+pub use crate::optimizer::{
+    backtest, get_default_vault, get_simulation, get_start_vault, Backtest, Scenario,
+    ScenarioResult, SIMULATION_START_PRICE,
+};
+pub use crate::permissions::{get_permissions, Access, Permission};
+pub use crate::planner::{
+    get_close_plan, get_leverage_plan, ClosePlan, LeverStep, Leverage, LeveragePlan, PlannerError,
+    Unwind, UnwindStep,
+};
+pub use crate::proxy_registry::ProxyRegistry;
+pub use crate::saver::{IlkInfo, Saver, SettingsAction, SettingsChange};
+pub use crate::simulation::{
+    read_ticks, simulate_path, EquityPoint, MonteCarlo, MonteCarloReport, PathResult, Rng,
+    SimulationError, Tick,
+};
 pub use crate::spot::Spot;
-pub use crate::uniswapv2_pair::Pair;
-pub use crate::vault::Vault;
+pub use crate::subscribers::SubscriberStats;
+pub use crate::uniswapv2_factory::Factory;
+pub use crate::uniswapv2_pair::{Pair, PairError, Pool, SwapQuote};
+pub use crate::vat::Vat;
+pub use crate::vault::{
+    AutomationAction, AutomationStep, Close, CloseRule, CloseTo, CloseTrigger, Equity, Friction,
+    Liquidation, LiquidationTerms, PricePoint, Trace, TradeCost, VatLimits, Vault, VaultError,
+    VaultInfo,
+};
@@ -0,0 +1,390 @@
+use super::blockchain::BlockchainReader;
+use super::cdp_manager::{CdpManager, MCD_DEPLOYMENT_BLOCK};
+use super::math::Wad;
+use super::median::Median;
+use super::saver::Saver;
+use super::spot::Spot;
+use super::vault::{AutomationAction, Friction, TradeCost, Vault};
+use ethabi::{decode, Address, Contract, Hash, ParamType, Uint};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use web3::signing::keccak256;
+use web3::types::{BlockNumber, FilterBuilder};
+
+const LOGGER_ADDRESS: &str = "5c55b921f590a89c1ebe84df170e655a82b62126";
+
+/// A boost or a repay as it happened on chain.
+#[derive(Debug, Clone)]
+pub struct Execution {
+    pub vault_id: Uint,
+    pub action: AutomationAction,
+    pub block_number: u64,
+    pub timestamp: u64,
+    pub transaction_hash: Hash,
+    /// Collateral bought by a boost or sold by a repay, with the token decimals.
+    pub collateral: Uint,
+    /// DAI drawn by a boost or paid back by a repay.
+    pub dai: Wad,
+    pub gas_used: Uint,
+    pub gas_price: Uint,
+}
+
+impl Execution {
+    /// The transaction cost, in ETH.
+    pub fn get_gas_fee(&self) -> Wad {
+        Wad(self.gas_used * self.gas_price)
+    }
+
+    /// The share of the traded value lost between the oracle `price` and the execution.
+    pub fn get_friction(&self, price: Wad, decimals: usize) -> Result<f64, Box<dyn Error>> {
+        let col_dai = Wad::from_decimals(self.collateral, decimals)?
+            .checked_mul(price)?
+            .to_f64();
+        let dai = self.dai.to_f64();
+        Ok(match self.action {
+            AutomationAction::Boost => 1.0 - col_dai / dai,
+            AutomationAction::Repay => 1.0 - dai / col_dai,
+        })
+    }
+
+    /// What `Vault::get_up_vault` or `Vault::get_down_vault` predicts for this action,
+    /// starting from `vault` and triggered at `price`.
+    pub fn predict(&self, vault: &Vault, price: Wad) -> Result<Vault, Box<dyn Error>> {
+        let mut vault = vault.clone();
+        let ratio = vault
+            .collateral
+            .checked_mul(price)?
+            .checked_div(vault.debt)?;
+        let friction = Friction::Constant(0.0);
+        match self.action {
+            AutomationAction::Boost => {
+                vault.max_ratio = ratio;
+                vault.get_up_vault(&friction)
+            }
+            AutomationAction::Repay => {
+                vault.min_ratio = ratio;
+                vault.get_down_vault(&friction)
+            }
+        }
+    }
+}
+
+/// An execution put side by side with the vault just before it.
+#[derive(Debug, Clone)]
+pub struct ExecutionReport {
+    pub execution: Execution,
+    /// The oracle price in the block before the execution.
+    pub price: Wad,
+    /// The ETH oracle price at the same block, to convert the gas fee.
+    pub eth_price: Wad,
+    pub vault: Vault,
+    /// `None` when the vault was not subscribed or had no debt, there is nothing to predict with.
+    pub predicted: Option<Vault>,
+    pub friction: f64,
+}
+
+pub struct Logger<'a> {
+    blockchain_reader: &'a dyn BlockchainReader,
+    logger_address: Address,
+    logger_contract: Contract,
+}
+
+impl<'a> Logger<'a> {
+    pub fn new(blockchain_reader: &'a (dyn BlockchainReader + 'a)) -> Result<Self, Box<dyn Error>> {
+        let logger_address: Address = LOGGER_ADDRESS.parse()?;
+        let logger_abi: &[u8] = include_bytes!("abi/defisaver_logger.abi");
+        let logger_contract = Contract::load(logger_abi)?;
+        Ok(Self {
+            blockchain_reader,
+            logger_address,
+            logger_contract,
+        })
+    }
+
+    /// Every boost and repay executed on the vault (or on every vault), oldest first.
+    pub async fn get_executions(
+        &self,
+        vault_id: Option<Uint>,
+    ) -> Result<Vec<Execution>, Box<dyn Error>> {
+        let log_event = self.logger_contract.event("LogEvent")?.signature();
+        let boost = Hash::from(keccak256(b"MCDBoost"));
+        let repay = Hash::from(keccak256(b"MCDRepay"));
+        // the vault id is in the data, every boost and repay has to be read
+        let filter = FilterBuilder::default()
+            .address(vec![self.logger_address])
+            .topics(Some(vec![log_event]), None, None, Some(vec![boost, repay]));
+        let logs = self
+            .blockchain_reader
+            .get_logs_by_range(&filter, MCD_DEPLOYMENT_BLOCK, BlockNumber::Latest)
+            .await?;
+
+        let mut executions = Vec::new();
+        for log in logs {
+            // the event data is itself abi encoded bytes
+            let data = decode(&[ParamType::Bytes], &log.data.0)?;
+            let data = data[0].clone().to_bytes().unwrap();
+            let tokens = decode(
+                &[
+                    ParamType::Uint(256),
+                    ParamType::Address,
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                ],
+                &data,
+            )?;
+            let execution_vault_id = tokens[0].clone().to_uint().unwrap();
+            if vault_id.is_some() && vault_id != Some(execution_vault_id) {
+                continue;
+            }
+            let (action, collateral, dai) = match log.topics[3] == boost {
+                true => (
+                    AutomationAction::Boost,
+                    tokens[3].clone().to_uint().unwrap(),
+                    Wad(tokens[2].clone().to_uint().unwrap()),
+                ),
+                false => (
+                    AutomationAction::Repay,
+                    tokens[2].clone().to_uint().unwrap(),
+                    Wad(tokens[3].clone().to_uint().unwrap()),
+                ),
+            };
+            let transaction_hash = log.transaction_hash.unwrap_or_default();
+            let block_number = log.block_number.unwrap_or_default().as_u64();
+            let transaction = self
+                .blockchain_reader
+                .get_transaction(&transaction_hash)
+                .await?;
+            let receipt = self
+                .blockchain_reader
+                .get_transaction_receipt(&transaction_hash)
+                .await?;
+            executions.push(Execution {
+                vault_id: execution_vault_id,
+                action,
+                block_number,
+                timestamp: self
+                    .blockchain_reader
+                    .get_block_timestamp(block_number)
+                    .await?,
+                transaction_hash,
+                collateral,
+                dai,
+                gas_used: receipt.gas_used.unwrap_or_default(),
+                gas_price: transaction.gas_price,
+            });
+        }
+        Ok(executions)
+    }
+}
+
+/// Reads the executions of the vault (or of every vault) and the state the vault was in
+/// just before each of them. This needs an archive node.
+pub async fn get_execution_reports(
+    blockchain_reader: &dyn BlockchainReader,
+    vault_id: Option<Uint>,
+) -> Result<Vec<ExecutionReport>, Box<dyn Error>> {
+    let logger = Logger::new(blockchain_reader)?;
+    let saver = Saver::new(blockchain_reader)?;
+    let cdp_manager = CdpManager::new(blockchain_reader)?;
+    let spot = Spot::new(blockchain_reader)?;
+    let mut eth_ilk_id = [0u8; 32];
+    eth_ilk_id[..5].copy_from_slice(b"ETH-A");
+    let eth_median = Median::new(
+        blockchain_reader,
+        spot.get_median_address(&eth_ilk_id).await?,
+    )?;
+    let mut median_addresses: HashMap<Uint, Address> = HashMap::new();
+
+    let mut reports = Vec::new();
+    for execution in logger.get_executions(vault_id).await? {
+        let median_address = match median_addresses.get(&execution.vault_id) {
+            Some(median_address) => *median_address,
+            None => {
+                let ilk_id = cdp_manager.get_ilk_id(execution.vault_id).await?;
+                let median_address = spot.get_median_address(&ilk_id).await?;
+                median_addresses.insert(execution.vault_id, median_address);
+                median_address
+            }
+        };
+        let median = Median::new(blockchain_reader, median_address)?;
+        let block = BlockNumber::Number((execution.block_number - 1).into());
+        let price = median.get_price_at(false, block).await?;
+        let eth_price = eth_median.get_price_at(false, block).await?;
+        let vault = saver.get_vault_at(execution.vault_id, block).await?;
+        let predicted = match vault.subscribed {
+            true if !vault.debt.is_zero() => Some(execution.predict(&vault, price)?),
+            _ => None,
+        };
+        let friction = execution.get_friction(price, vault.token.decimals)?;
+        reports.push(ExecutionReport {
+            execution,
+            price,
+            eth_price,
+            vault,
+            predicted,
+            friction,
+        });
+    }
+    Ok(reports)
+}
+
+impl ExecutionReport {
+    /// The gas fee converted to DAI.
+    pub fn get_gas_cost(&self) -> Result<Wad, Box<dyn Error>> {
+        self.execution.get_gas_fee().checked_mul(self.eth_price)
+    }
+
+    /// The value traded, in DAI: drawn by a boost, or the collateral sold by a repay at the
+    /// oracle price.
+    pub fn get_traded_value(&self) -> Result<Wad, Box<dyn Error>> {
+        match self.execution.action {
+            AutomationAction::Boost => Ok(self.execution.dai),
+            AutomationAction::Repay => {
+                Wad::from_decimals(self.execution.collateral, self.vault.token.decimals)?
+                    .checked_mul(self.price)
+            }
+        }
+    }
+}
+
+/// Friction fitted on past executions, split between the boosts and the repays.
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    /// `None` without any boost to fit on.
+    pub boost: Option<TradeCost>,
+    /// `None` without any repay to fit on.
+    pub repay: Option<TradeCost>,
+    pub boosts: usize,
+    pub repays: usize,
+}
+
+impl Calibration {
+    /// The Saver charges the executor's gas on the trade itself, so the value lost by an
+    /// execution is its gas cost plus the slippage on what is left. The fixed cost is the
+    /// mean gas cost and the slippage is what remains of the losses, weighted by the value
+    /// traded. Executions that beat the oracle price on the whole are an error, they do not
+    /// make a cost.
+    pub fn fit(reports: &[ExecutionReport]) -> Result<Calibration, Box<dyn Error>> {
+        let (boost, boosts) = fit_trade_cost(reports, AutomationAction::Boost)?;
+        let (repay, repays) = fit_trade_cost(reports, AutomationAction::Repay)?;
+        Ok(Calibration {
+            boost,
+            repay,
+            boosts,
+            repays,
+        })
+    }
+
+    /// The calibrated friction, when both the boosts and the repays could be fitted.
+    pub fn get_friction(&self) -> Option<Friction> {
+        Some(Friction::Calibrated {
+            boost: self.boost?,
+            repay: self.repay?,
+        })
+    }
+}
+
+fn fit_trade_cost(
+    reports: &[ExecutionReport],
+    action: AutomationAction,
+) -> Result<(Option<TradeCost>, usize), Box<dyn Error>> {
+    let mut count = 0;
+    let mut gas = Wad::zero();
+    let mut traded = 0.0;
+    let mut lost = 0.0;
+    for report in reports.iter().filter(|r| r.execution.action == action) {
+        let traded_value = report.get_traded_value()?.to_f64();
+        count += 1;
+        gas = gas.checked_add(report.get_gas_cost()?)?;
+        traded += traded_value;
+        lost += report.friction * traded_value;
+    }
+    if count == 0 {
+        return Ok((None, count));
+    }
+    if traded <= 0.0 {
+        return Err(Box::new(CalibrationError(format!(
+            "the {} executions traded nothing",
+            action
+        ))));
+    }
+    // the losses are signed, they stay in f64
+    let slippage = (lost - gas.to_f64()) / traded;
+    if slippage < 0.0 {
+        return Err(Box::new(CalibrationError(format!(
+            "the {} executions lost {:.2}% less than their gas, there is no slippage to fit",
+            action,
+            -slippage * 100.0
+        ))));
+    }
+    let cost = TradeCost {
+        gas: Wad(gas.0 / Uint::from(count)),
+        slippage,
+    };
+    Ok((Some(cost), count))
+}
+
+#[derive(Debug, Clone)]
+pub struct CalibrationError(pub String);
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for CalibrationError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::get_default_vault;
+
+    /// A boost drawing `dai` for 0.01 ETH of gas at 2000 DAI, losing `friction` of it.
+    fn boost(dai: f64, friction: f64) -> ExecutionReport {
+        ExecutionReport {
+            execution: Execution {
+                vault_id: Uint::one(),
+                action: AutomationAction::Boost,
+                block_number: 1,
+                timestamp: 0,
+                transaction_hash: Hash::zero(),
+                collateral: Uint::zero(),
+                dai: Wad::from_f64(dai).unwrap(),
+                gas_used: Uint::from(100_000),
+                gas_price: Uint::from(100_000_000_000u64),
+            },
+            price: Wad::from_f64(100.0).unwrap(),
+            eth_price: Wad::from_f64(2000.0).unwrap(),
+            vault: get_default_vault().unwrap(),
+            predicted: None,
+            friction,
+        }
+    }
+
+    #[test]
+    fn fit_weights_the_slippage_by_the_value_traded() {
+        let reports = vec![boost(1000.0, 0.03), boost(3000.0, 0.02)];
+        let calibration = Calibration::fit(&reports).unwrap();
+        assert_eq!(calibration.boosts, 2);
+        let cost = calibration.boost.unwrap();
+        assert_eq!(cost.gas, Wad::from_f64(20.0).unwrap());
+        // 90 dai lost, 40 of them in gas, on 4000 traded
+        assert!((cost.slippage - 0.0125).abs() < 1e-12);
+        // no repay to fit on
+        assert_eq!(calibration.repays, 0);
+        assert!(calibration.repay.is_none());
+        assert!(calibration.get_friction().is_none());
+    }
+
+    #[test]
+    fn fit_rejects_a_negative_slippage() {
+        // 10 dai lost for 20 of gas
+        assert!(Calibration::fit(&[boost(1000.0, 0.01)]).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use ethabi::Uint;
+use std::error::Error;
+use std::fmt;
+
+macro_rules! fixed_point {
+    ($name:ident, $decimals:expr) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+        pub struct $name(pub Uint);
+
+        impl $name {
+            pub const DECIMALS: usize = $decimals;
+
+            pub fn zero() -> Self {
+                Self(Uint::zero())
+            }
+
+            pub fn one() -> Self {
+                Self(Uint::exp10($decimals))
+            }
+
+            pub fn is_zero(&self) -> bool {
+                self.0.is_zero()
+            }
+
+            /// Parses a user input, `value` must be finite and positive.
+            pub fn from_f64(value: f64) -> Result<Self, Box<dyn Error>> {
+                if !value.is_finite() || value < 0.0 {
+                    return Err(Box::new(MathError(format!(
+                        "{} is not a valid {}",
+                        value,
+                        stringify!($name)
+                    ))));
+                }
+                // f64 holds about 16 significant digits, the micro units are enough
+                let micro = Uint::from((value * 1e6).round() as u128);
+                Ok(Self(checked(
+                    micro.checked_mul(Uint::exp10($decimals - 6)),
+                    "from_f64",
+                )?))
+            }
+
+            /// For display only, the precision is the one of an f64.
+            pub fn to_f64(self) -> f64 {
+                to_f64(self.0) / 10f64.powi($decimals)
+            }
+
+            pub fn checked_add(self, other: Self) -> Result<Self, Box<dyn Error>> {
+                Ok(Self(checked(self.0.checked_add(other.0), "add")?))
+            }
+
+            pub fn checked_sub(self, other: Self) -> Result<Self, Box<dyn Error>> {
+                Ok(Self(checked(self.0.checked_sub(other.0), "sub")?))
+            }
+
+            pub fn saturating_sub(self, other: Self) -> Self {
+                Self(self.0.saturating_sub(other.0))
+            }
+
+            /// Rounds to the nearest unit, as `wmul` and `rmul` do in ds-math.
+            pub fn checked_mul(self, other: Self) -> Result<Self, Box<dyn Error>> {
+                Ok(Self(mul_round(self.0, other.0, Uint::exp10($decimals))?))
+            }
+
+            /// Rounds to the nearest unit, as `wdiv` and `rdiv` do in ds-math.
+            pub fn checked_div(self, other: Self) -> Result<Self, Box<dyn Error>> {
+                Ok(Self(div_round(self.0, Uint::exp10($decimals), other.0)?))
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Display::fmt(&self.to_f64(), f)
+            }
+        }
+    };
+}
+
+fixed_point!(Wad, 18);
+fixed_point!(Ray, 27);
+fixed_point!(Rad, 45);
+
+impl Wad {
+    /// A ratio given in %, e.g. 150 for a 150% ratio.
+    pub fn from_percent(percent: usize) -> Self {
+        Self(Uint::from(percent) * Uint::exp10(16))
+    }
+
+    pub fn to_percent(self) -> f64 {
+        to_f64(self.0) / 1e16
+    }
+
+    /// Converts a token amount with `decimals` decimals.
+    pub fn from_decimals(amount: Uint, decimals: usize) -> Result<Self, Box<dyn Error>> {
+        match decimals <= 18 {
+            true => Ok(Self(checked(
+                amount.checked_mul(Uint::exp10(18 - decimals)),
+                "from_decimals",
+            )?)),
+            false => Ok(Self(amount / Uint::exp10(decimals - 18))),
+        }
+    }
+
+    /// Converts to a token amount with `decimals` decimals, rounding down.
+    pub fn to_decimals(self, decimals: usize) -> Result<Uint, Box<dyn Error>> {
+        match decimals <= 18 {
+            true => Ok(self.0 / Uint::exp10(18 - decimals)),
+            false => checked(
+                self.0.checked_mul(Uint::exp10(decimals - 18)),
+                "to_decimals",
+            ),
+        }
+    }
+
+    pub fn to_ray(self) -> Result<Ray, Box<dyn Error>> {
+        Ok(Ray(checked(self.0.checked_mul(Uint::exp10(9)), "to_ray")?))
+    }
+
+    /// Multiplies by a ray and rounds to the nearest wad, as `rmul` does.
+    pub fn checked_mul_ray(self, ray: Ray) -> Result<Self, Box<dyn Error>> {
+        Ok(Self(mul_round(self.0, ray.0, Uint::exp10(27))?))
+    }
+}
+
+impl Ray {
+    /// Rounds down, like the Vat does when it moves from rays to wads.
+    pub fn to_wad(self) -> Wad {
+        Wad(self.0 / Uint::exp10(9))
+    }
+
+    /// Exponentiation by squaring, rounding each product as `rpow` does.
+    pub fn checked_pow(self, mut exponent: u64) -> Result<Self, Box<dyn Error>> {
+        let mut base = self;
+        let mut result = Ray::one();
+        while exponent > 0 {
+            if exponent % 2 == 1 {
+                result = result.checked_mul(base)?;
+            }
+            exponent /= 2;
+            if exponent > 0 {
+                base = base.checked_mul(base)?;
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Rad {
+    /// The exact product the Vat keeps, e.g. `art * rate` for the debt.
+    pub fn from_product(wad: Wad, ray: Ray) -> Result<Self, Box<dyn Error>> {
+        Ok(Self(checked(wad.0.checked_mul(ray.0), "from_product")?))
+    }
+
+    /// Rounds down, like the Vat does when it moves from rads to wads.
+    pub fn to_wad(self) -> Wad {
+        Wad(self.0 / Uint::exp10(27))
+    }
+}
+
+fn checked(value: Option<Uint>, operation: &str) -> Result<Uint, Box<dyn Error>> {
+    match value {
+        Some(value) => Ok(value),
+        None => Err(Box::new(MathError(format!("{} overflow", operation)))),
+    }
+}
+
+/// `(x * y + unit / 2) / unit`
+fn mul_round(x: Uint, y: Uint, unit: Uint) -> Result<Uint, Box<dyn Error>> {
+    let product = checked(x.checked_mul(y), "mul")?;
+    let product = checked(product.checked_add(unit / 2), "mul")?;
+    Ok(product / unit)
+}
+
+/// `(x * unit + y / 2) / y`
+fn div_round(x: Uint, unit: Uint, y: Uint) -> Result<Uint, Box<dyn Error>> {
+    if y.is_zero() {
+        return Err(Box::new(MathError(String::from("division by zero"))));
+    }
+    let product = checked(x.checked_mul(unit), "div")?;
+    let product = checked(product.checked_add(y / 2), "div")?;
+    Ok(product / y)
+}
+
+fn to_f64(value: Uint) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 18446744073709551616.0 + *limb as f64)
+}
+
+#[derive(Debug, Clone)]
+pub struct MathError(pub String);
+
+impl fmt::Display for MathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for MathError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wmul_rounds_half_up() {
+        let half = Wad(Uint::exp10(17) * Uint::from(5));
+        assert_eq!(
+            Wad(Uint::one()).checked_mul(half).unwrap(),
+            Wad(Uint::one())
+        );
+        let below_half = Wad(half.0 - Uint::one());
+        assert_eq!(
+            Wad(Uint::one()).checked_mul(below_half).unwrap(),
+            Wad::zero()
+        );
+        let product = Wad::from_f64(1.5)
+            .unwrap()
+            .checked_mul(Wad::from_f64(2.0).unwrap());
+        assert_eq!(product.unwrap(), Wad::from_f64(3.0).unwrap());
+    }
+
+    #[test]
+    fn rdiv_rounds_half_up() {
+        let three = Ray(Uint::exp10(27) * Uint::from(3));
+        // 2 / 3 units is 0.67, 1 / 3 is 0.33
+        assert_eq!(
+            Ray(Uint::from(2)).checked_div(three).unwrap(),
+            Ray(Uint::one())
+        );
+        assert_eq!(Ray(Uint::one()).checked_div(three).unwrap(), Ray::zero());
+        let two = Ray(Uint::exp10(27) * Uint::from(2));
+        assert_eq!(Ray(Uint::one()).checked_div(two).unwrap(), Ray(Uint::one()));
+    }
+
+    #[test]
+    fn overflows_are_errors() {
+        let max = Wad(Uint::max_value());
+        assert!(max.checked_add(Wad(Uint::one())).is_err());
+        assert!(Wad::zero().checked_sub(Wad(Uint::one())).is_err());
+        assert!(max.checked_mul(Wad::from_f64(2.0).unwrap()).is_err());
+        assert!(max.checked_div(Wad::one()).is_err());
+        assert!(Wad::one().checked_div(Wad::zero()).is_err());
+        assert!(max.to_ray().is_err());
+        assert!(Wad::from_decimals(Uint::max_value(), 6).is_err());
+        assert_eq!(Wad::zero().saturating_sub(Wad::one()), Wad::zero());
+    }
+
+    #[test]
+    fn checked_pow_squares_and_rounds_like_rpow() {
+        let two = Ray(Uint::exp10(27) * Uint::from(2));
+        assert_eq!(two.checked_pow(0).unwrap(), Ray::one());
+        assert_eq!(two.checked_pow(1).unwrap(), two);
+        assert_eq!(
+            two.checked_pow(10).unwrap(),
+            Ray(Uint::exp10(27) * Uint::from(1024))
+        );
+        let half = Ray(Uint::exp10(26) * Uint::from(5));
+        assert_eq!(
+            half.checked_pow(3).unwrap(),
+            Ray(Uint::exp10(24) * Uint::from(125))
+        );
+        // one unit above one, a whole day of seconds stays exact to the unit
+        let rate = Ray(Uint::exp10(27) + Uint::one());
+        assert_eq!(
+            rate.checked_pow(86400).unwrap(),
+            Ray(Uint::exp10(27) + Uint::from(86400))
+        );
+        assert!(two.checked_pow(300).is_err());
+    }
+
+    #[test]
+    fn from_f64_rejects_negative_and_infinite_values() {
+        assert!(Wad::from_f64(-1.0).is_err());
+        assert!(Wad::from_f64(f64::NAN).is_err());
+        assert!(Wad::from_f64(f64::INFINITY).is_err());
+        assert_eq!(Wad::from_f64(1.5).unwrap(), Wad::from_percent(150));
+    }
+}
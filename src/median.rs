@@ -1,6 +1,15 @@
 use super::blockchain::BlockchainReader;
-use ethabi::{Address, Uint};
+use super::math::Wad;
+use super::simulation::Tick;
+use ethabi::{Address, Hash, Uint};
+use std::collections::HashMap;
 use std::error::Error;
+use web3::signing::keccak256;
+use web3::types::{BlockNumber, FilterBuilder};
+
+/// The timestamps of the pokes are interpolated between blocks this far apart, about 18
+/// hours, rather than read one by one. The block time is steady enough for the minute.
+const TIMESTAMP_ANCHOR_BLOCKS: u64 = 5_000;
 
 pub struct Median<'a> {
     blockchain_reader: &'a dyn BlockchainReader,
@@ -18,16 +27,84 @@ impl<'a> Median<'a> {
         })
     }
 
-    pub async fn get_price(&self, next: bool) -> Result<Uint, Box<dyn Error>> {
+    pub async fn get_price(&self, next: bool) -> Result<Wad, Box<dyn Error>> {
+        self.get_price_at(next, BlockNumber::Latest).await
+    }
+
+    pub async fn get_price_at(
+        &self,
+        next: bool,
+        block: BlockNumber,
+    ) -> Result<Wad, Box<dyn Error>> {
         let position = match next {
             true => Uint::from(4),
             false => Uint::from(3),
         };
         let data: Vec<u8> = self
             .blockchain_reader
-            .get_storage_at(&self.median_address, position)
+            .get_storage_at_block(&self.median_address, position, block)
             .await?;
         let data: Vec<u8> = data.iter().rev().take(16).rev().cloned().collect();
-        Ok(Uint::from_big_endian(&data))
+        Ok(Wad(Uint::from_big_endian(&data)))
+    }
+
+    /// Every price pushed by `poke` between the two blocks, oldest first. The OSM only makes
+    /// each of them current at the next poke, an hour later.
+    pub async fn get_price_history(
+        &self,
+        from_block: u64,
+        to_block: BlockNumber,
+    ) -> Result<Vec<Tick>, Box<dyn Error>> {
+        let log_value = Hash::from(keccak256(b"LogValue(bytes32)"));
+        let filter = FilterBuilder::default()
+            .address(vec![self.median_address])
+            .topics(Some(vec![log_value]), None, None, None);
+        let logs = self
+            .blockchain_reader
+            .get_logs_by_range(&filter, from_block, to_block)
+            .await?;
+        let last_block = logs
+            .iter()
+            .filter_map(|log| log.block_number)
+            .max()
+            .unwrap_or_default()
+            .as_u64();
+        let mut anchors: HashMap<u64, u64> = HashMap::new();
+        let mut ticks = Vec::with_capacity(logs.len());
+        for log in logs {
+            let block_number = log.block_number.unwrap_or_default().as_u64();
+            let low = block_number - block_number % TIMESTAMP_ANCHOR_BLOCKS;
+            let high = (low + TIMESTAMP_ANCHOR_BLOCKS).min(last_block);
+            let low_timestamp = self.get_anchor(&mut anchors, low).await?;
+            let timestamp = match block_number == low {
+                true => low_timestamp,
+                false => {
+                    let high_timestamp = self.get_anchor(&mut anchors, high).await?;
+                    low_timestamp
+                        + (high_timestamp - low_timestamp) * (block_number - low) / (high - low)
+                }
+            };
+            ticks.push(Tick {
+                timestamp: Some(timestamp),
+                price: Wad(Uint::from_big_endian(&log.data.0)),
+            });
+        }
+        Ok(ticks)
+    }
+
+    async fn get_anchor(
+        &self,
+        anchors: &mut HashMap<u64, u64>,
+        block_number: u64,
+    ) -> Result<u64, Box<dyn Error>> {
+        if let Some(timestamp) = anchors.get(&block_number) {
+            return Ok(*timestamp);
+        }
+        let timestamp = self
+            .blockchain_reader
+            .get_block_timestamp(block_number)
+            .await?;
+        anchors.insert(block_number, timestamp);
+        Ok(timestamp)
     }
 }
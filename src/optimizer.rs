@@ -1,5 +1,7 @@
 use super::erc_20::Erc20Token;
-use super::vault::Vault;
+use super::math::Wad;
+use super::simulation::{simulate_path, PathResult, SimulationError, Tick};
+use super::vault::{CloseRule, Equity, Friction, LiquidationTerms, Vault};
 use ethabi::Uint;
 use std::error::Error;
 use std::fmt;
@@ -17,7 +19,11 @@ pub struct Scenario {
     pub boost_ratio: usize,
     pub price_increase: f64,
     pub no_boost_increase: f64,
-    pub friction: f64,
+    pub friction: Friction,
+    /// Applied to every simulated vault.
+    pub close_rules: Vec<CloseRule>,
+    /// The same scenario without the close rules, `None` when there is none.
+    pub without_rules: Option<Box<Scenario>>,
 }
 
 impl fmt::Display for Scenario {
@@ -31,15 +37,49 @@ impl fmt::Display for Scenario {
             self.best_result.max_ratio,
             self.boost_ratio,
             self.best_result.increase
-        )
+        )?;
+        if let Some(without_rules) = &self.without_rules {
+            write!(
+                f,
+                ", without the close rules {}%: x{:>5.2}",
+                without_rules.best_result.max_ratio, without_rules.best_result.increase
+            )?;
+        }
+        Ok(())
     }
 }
 
+/// A subscribed vault of an anonymous 18 decimals token, the base of every simulation.
+pub fn get_default_vault() -> Result<Vault, Box<dyn Error>> {
+    Ok(Vault {
+        subscribed: true,
+        min_ratio: Wad::from_percent(100),
+        max_ratio: Wad::from_percent(220),
+        repay_ratio: Wad::from_percent(180),
+        boost_ratio: Wad::from_percent(180),
+        owner: "0000000000000000000000000000000000000000".parse()?,
+        collateral: Wad::from_f64(180.0)?,
+        debt: Wad::from_f64(10000.0)?,
+        token: Erc20Token {
+            name: "".to_string(),
+            symbol: "".to_string(),
+            decimals: 18,
+            address: "0000000000000000000000000000000000000000".parse()?,
+        },
+        close_rules: Vec::new(),
+        dai: Wad::zero(),
+    })
+}
+
+/// The price every simulation starts from, the close rules are given against it.
+pub const SIMULATION_START_PRICE: f64 = 100.0;
+
 pub fn get_simulation(
     boost_ratio: usize,
     price_increase: f64,
-    friction: f64,
+    friction: &Friction,
     end: usize,
+    close_rules: &[CloseRule],
 ) -> Result<Scenario, Box<dyn Error>> {
     let mut scenario = Scenario {
         results: Vec::with_capacity(end),
@@ -50,43 +90,42 @@ pub fn get_simulation(
         boost_ratio,
         price_increase,
         no_boost_increase: 0.0,
-        friction,
-    };
-    let mut vault = Vault {
-        subscribed: true,
-        min_ratio: Uint::from(100) * Uint::exp10(16),
-        max_ratio: Uint::from(220) * Uint::exp10(16),
-        repay_ratio: Uint::from(180) * Uint::exp10(16),
-        boost_ratio: Uint::from(180) * Uint::exp10(16),
-        owner: "0000000000000000000000000000000000000000".parse()?,
-        collateral: Uint::from(180) * Uint::exp10(18),
-        debt: Uint::from(10000) * Uint::exp10(18),
-        token: Erc20Token {
-            name: "".to_string(),
-            symbol: "".to_string(),
-            decimals: 18,
-            address: "0000000000000000000000000000000000000000".parse()?,
+        friction: friction.clone(),
+        close_rules: close_rules.to_vec(),
+        without_rules: match close_rules.is_empty() {
+            true => None,
+            false => Some(Box::new(get_simulation(
+                boost_ratio,
+                price_increase,
+                friction,
+                end,
+                &[],
+            )?)),
         },
     };
+    let mut vault = get_default_vault()?;
+    vault.close_rules = close_rules.to_vec();
 
     if end < 1 {
         return Ok(scenario);
     }
-    let base_price = 100;
-    let start_price = Uint::from(base_price) * Uint::exp10(18);
-    let up_price = Uint::from((start_price.as_u128() as f64 * price_increase) as u128);
-    vault.boost_ratio = Uint::from(boost_ratio) * Uint::exp10(16);
-    vault.collateral = vault.debt * vault.boost_ratio / start_price;
+    let start_price = Wad::from_f64(SIMULATION_START_PRICE)?;
+    let up_price = start_price.checked_mul(Wad::from_f64(price_increase)?)?;
+    vault.boost_ratio = Wad::from_percent(boost_ratio);
+    vault.collateral = vault
+        .debt
+        .checked_mul(vault.boost_ratio)?
+        .checked_div(start_price)?;
     let base = vault.get_dai_value(start_price)?;
     let base_up = vault.get_dai_value(up_price)?;
     for max_ratio in (boost_ratio + 1)..=(boost_ratio + end) {
-        vault.max_ratio = Uint::from(max_ratio) * Uint::exp10(16);
+        vault.max_ratio = Wad::from_percent(max_ratio);
         let r = vault.predict_vault(up_price, friction);
         let dai_value = match r {
             Ok(v2) => v2.get_dai_value(up_price)?,
-            _ => Uint::zero(),
+            _ => Wad::zero(),
         };
-        let increase = dai_value.as_u128() as f64 / base.as_u128() as f64;
+        let increase = dai_value.checked_div(base)?.to_f64();
         let scenario_result = ScenarioResult {
             max_ratio,
             increase,
@@ -96,6 +135,171 @@ pub fn get_simulation(
             scenario.best_result = scenario_result;
         }
     }
-    scenario.no_boost_increase = base_up.as_u128() as f64 / base.as_u128() as f64;
+    scenario.no_boost_increase = base_up.checked_div(base)?.to_f64();
     Ok(scenario)
 }
+
+/// A vault replayed over historical prices, next to what holding would have given.
+#[derive(Debug, Clone)]
+pub struct Backtest {
+    pub result: PathResult,
+    pub start_equity: Wad,
+    /// The starting equity held as collateral, without any debt.
+    pub hodl_equity: Wad,
+    /// The starting vault without boosts, repays or close rules, liquidated on the way if
+    /// the path takes it there.
+    pub unautomated_equity: Equity,
+    /// The largest fall of the equity from a previous high, as a share of that high.
+    pub max_drawdown: f64,
+    /// The same vault with boosts and repays only, `None` when it has no close rule.
+    pub without_rules: Option<PathResult>,
+}
+
+/// `settings` with `equity` DAI of collateral levered up to `ratio` at `price`.
+pub fn get_start_vault(
+    settings: &Vault,
+    price: Wad,
+    equity: Wad,
+    ratio: Wad,
+) -> Result<Vault, Box<dyn Error>> {
+    if ratio <= Wad::one() {
+        return Err(Box::new(SimulationError(format!(
+            "the start ratio ({:.0}%) must be above 100%",
+            ratio.to_percent()
+        ))));
+    }
+    let mut vault = settings.clone();
+    // equity = debt * (ratio - 1)
+    vault.debt = equity.checked_div(ratio.checked_sub(Wad::one())?)?;
+    vault.collateral = vault.debt.checked_mul(ratio)?.checked_div(price)?;
+    Ok(vault)
+}
+
+/// Replays `vault` along `ticks` and compares it with holding and with the same vault left
+/// alone, see `simulate_path` for `delay` and `terms`.
+pub fn backtest(
+    vault: &Vault,
+    ticks: &[Tick],
+    friction: &Friction,
+    delay: u64,
+    terms: &LiquidationTerms,
+) -> Result<Backtest, Box<dyn Error>> {
+    let (first, last) = match (ticks.first(), ticks.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            return Err(Box::new(SimulationError(String::from(
+                "no price to backtest",
+            ))))
+        }
+    };
+    let result = simulate_path(vault, ticks, friction, delay, terms)?;
+    let without_rules = match vault.close_rules.is_empty() {
+        true => None,
+        false => {
+            let mut vault = vault.clone();
+            vault.close_rules.clear();
+            Some(simulate_path(&vault, ticks, friction, delay, terms)?)
+        }
+    };
+    let start_equity = vault
+        .collateral
+        .checked_mul(first.price)?
+        .saturating_sub(vault.debt);
+    let hodl_equity = start_equity
+        .checked_div(first.price)?
+        .checked_mul(last.price)?;
+    let mut unautomated = vault.clone();
+    // no ratio triggers a step and nothing closes the vault
+    unautomated.min_ratio = Wad::zero();
+    unautomated.max_ratio = Wad(Uint::from(u128::MAX));
+    unautomated.close_rules.clear();
+    let unautomated_equity =
+        simulate_path(&unautomated, ticks, friction, delay, terms)?.get_final_equity();
+    let mut peak = start_equity.to_f64();
+    let mut max_drawdown: f64 = 0.0;
+    for point in &result.points {
+        let equity = point.equity.to_f64();
+        if equity > peak {
+            peak = equity;
+        } else if peak > 0.0 {
+            max_drawdown = max_drawdown.max((peak - equity) / peak);
+        }
+    }
+    Ok(Backtest {
+        result,
+        start_equity,
+        hodl_equity,
+        unautomated_equity,
+        max_drawdown,
+        without_rules,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::{CloseTo, CloseTrigger};
+
+    fn ticks(prices: &[f64]) -> Vec<Tick> {
+        prices
+            .iter()
+            .map(|price| Tick {
+                timestamp: None,
+                price: Wad::from_f64(*price).unwrap(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn backtest_liquidates_the_unautomated_vault_in_a_crash() {
+        let mut vault = get_default_vault().unwrap();
+        vault.min_ratio = Wad::from_percent(160);
+        let terms = LiquidationTerms {
+            ratio: Wad::from_percent(150),
+            penalty: Wad::from_percent(113),
+            auction: None,
+        };
+        let ticks = ticks(&[100.0, 90.0, 80.0, 50.0]);
+        let backtest = backtest(&vault, &ticks, &Friction::Constant(0.0), 0, &terms).unwrap();
+        // the repays keep the automated vault above the liquidation ratio
+        assert!(backtest.result.liquidation.is_none());
+        assert!(backtest.result.repays > 0);
+        // left alone, it is bitten at 80 for 11300 dai, 141.25 of its 180 collateral, and
+        // is not underwater at 50 as the untouched vault would be
+        assert_eq!(
+            backtest.unautomated_equity,
+            Equity::Positive(Wad::from_f64(1937.5).unwrap())
+        );
+        assert_eq!(backtest.hodl_equity, Wad::from_f64(4000.0).unwrap());
+    }
+
+    #[test]
+    fn simulation_compares_the_close_rules_with_none() {
+        let friction = Friction::Constant(0.0);
+        let scenario = get_simulation(180, 2.0, &friction, 40, &[]).unwrap();
+        assert!(scenario.without_rules.is_none());
+        // the jump goes through the take-profit, the vault is closed at 150
+        let take_profit = CloseRule {
+            trigger: CloseTrigger::TakeProfit(Wad::from_f64(150.0).unwrap()),
+            to: CloseTo::Dai,
+        };
+        let closed = get_simulation(180, 2.0, &friction, 40, &[take_profit]).unwrap();
+        let without_rules = closed.without_rules.as_ref().unwrap();
+        assert_eq!(
+            without_rules.best_result.increase,
+            scenario.best_result.increase
+        );
+        assert!(closed.best_result.increase < without_rules.best_result.increase);
+    }
+
+    #[test]
+    fn backtest_needs_prices() {
+        let vault = get_default_vault().unwrap();
+        let terms = LiquidationTerms {
+            ratio: Wad::from_percent(150),
+            penalty: Wad::from_percent(113),
+            auction: None,
+        };
+        assert!(backtest(&vault, &[], &Friction::Constant(0.0), 0, &terms).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use super::blockchain::BlockchainReader;
+use super::cdp_manager::CdpManager;
+use super::ds_guard::DsGuard;
+use super::ds_proxy::DsProxy;
+use super::proxy_registry::ProxyRegistry;
+use ethabi::{Address, Uint};
+use std::error::Error;
+use std::fmt;
+
+/// Why an address is able to move the vault collateral or debt.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    /// Owns the vault in the CdpManager.
+    Owner,
+    /// Owns the DSProxy owning the vault.
+    ProxyOwner,
+    /// Permitted by the DSProxy authority to call `execute`.
+    ProxyAuthority,
+    /// Allowed on the vault with `cdpAllow`.
+    CdpAllow,
+    /// Allowed on every urn of the owner with `urnAllow`.
+    UrnAllow,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = match self {
+            Access::Owner => "owner",
+            Access::ProxyOwner => "proxy owner",
+            Access::ProxyAuthority => "proxy authority",
+            Access::CdpAllow => "cdpAllow",
+            Access::UrnAllow => "urnAllow",
+        };
+        f.pad(access)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Permission {
+    pub address: Address,
+    pub access: Access,
+}
+
+/// Lists every address that can operate the vault, as of the latest block.
+pub async fn get_permissions(
+    blockchain_reader: &dyn BlockchainReader,
+    vault_id: Uint,
+) -> Result<Vec<Permission>, Box<dyn Error>> {
+    let cdp_manager = CdpManager::new(blockchain_reader)?;
+    let proxy_registry = ProxyRegistry::new(blockchain_reader)?;
+    let owner = cdp_manager.get_owner(vault_id).await?;
+    let mut permissions = vec![Permission {
+        address: owner,
+        access: Access::Owner,
+    }];
+
+    if let Some(proxy_owner) = proxy_registry.get_owner(&owner).await? {
+        permissions.push(Permission {
+            address: proxy_owner,
+            access: Access::ProxyOwner,
+        });
+        let ds_proxy = DsProxy::new(blockchain_reader, owner)?;
+        let authority = ds_proxy.get_authority().await?;
+        if !authority.is_zero() {
+            let ds_guard = DsGuard::new(blockchain_reader, authority)?;
+            let execute = ds_proxy.get_execute_selector()?;
+            for address in ds_guard.get_permitted(&owner, execute).await? {
+                permissions.push(Permission {
+                    address,
+                    access: Access::ProxyAuthority,
+                });
+            }
+        }
+    }
+
+    for address in cdp_manager.get_cdp_allowed(vault_id).await? {
+        permissions.push(Permission {
+            address,
+            access: Access::CdpAllow,
+        });
+    }
+    for address in cdp_manager.get_urn_allowed(&owner).await? {
+        permissions.push(Permission {
+            address,
+            access: Access::UrnAllow,
+        });
+    }
+    Ok(permissions)
+}
@@ -0,0 +1,600 @@
+use super::math::Wad;
+use super::uniswapv2_pair::Pool;
+use super::vault::{VatLimits, Vault};
+use ethabi::Uint;
+use std::error::Error;
+use std::fmt;
+
+/// Past this many withdraw, sell and repay rounds, an iterative unwind is considered endless.
+const MAX_UNWIND_STEPS: usize = 100;
+/// Past this many draw, buy and deposit rounds, an iterative boost is considered endless.
+const MAX_LEVERAGE_STEPS: usize = 100;
+/// An iterative boost stops once the ratio is this close above the target.
+const BOOST_TOLERANCE: usize = 1;
+
+/// How the collateral locked by the debt is freed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unwind {
+    /// A single transaction borrows the DAI, repays the debt, sells the freed collateral and
+    /// returns the loan.
+    FlashLoan,
+    /// Each round withdraws what the liquidation ratio allows, sells it and repays.
+    Iterative,
+}
+
+impl fmt::Display for Unwind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unwind = match self {
+            Unwind::FlashLoan => "flash loan",
+            Unwind::Iterative => "iterative repay",
+        };
+        f.pad(unwind)
+    }
+}
+
+/// How the DAI drawn is turned into collateral.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Leverage {
+    /// A single transaction borrows the DAI, buys the collateral, deposits it, draws the debt
+    /// and returns the loan.
+    FlashLoan,
+    /// Each round draws what the liquidation ratio allows, buys collateral and deposits it.
+    Iterative,
+}
+
+impl fmt::Display for Leverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let leverage = match self {
+            Leverage::FlashLoan => "flash loan",
+            Leverage::Iterative => "iterative boost",
+        };
+        f.pad(leverage)
+    }
+}
+
+/// A sale of collateral on the pool and the repay it pays for.
+#[derive(Debug, Clone)]
+pub struct UnwindStep {
+    pub collateral_sold: Wad,
+    pub dai_received: Wad,
+    pub debt: Wad,
+    /// At the oracle price, `None` once the debt is repaid.
+    pub ratio: Option<Wad>,
+}
+
+/// What repaying part or all of the debt of a vault costs at the current price.
+#[derive(Debug, Clone)]
+pub struct ClosePlan {
+    pub unwind: Unwind,
+    pub debt_repaid: Wad,
+    /// One step for a flash loan, every round of an iterative repay.
+    pub steps: Vec<UnwindStep>,
+    pub collateral_sold: Wad,
+    /// The uniswap fee, in DAI at the pool mid price.
+    pub fees: Wad,
+    /// The value lost to the price impact past the fee, in DAI.
+    pub slippage: Wad,
+    pub flash_loan_fee: Wad,
+    /// The vault once unwound, its remaining collateral free to withdraw with no debt left.
+    pub vault: Vault,
+}
+
+impl ClosePlan {
+    /// Every cost of the plan, in DAI.
+    pub fn get_cost(&self) -> Result<Wad, Box<dyn Error>> {
+        self.fees
+            .checked_add(self.slippage)?
+            .checked_add(self.flash_loan_fee)
+    }
+}
+
+/// Plans repaying `dai` of the debt of `vault`, all of it when `None`, selling its
+/// collateral on `pool`. The Vat checks the vault against `limits` at the oracle `price`,
+/// and a flash loan costs `flash_fee` of the amount borrowed.
+pub fn get_close_plan(
+    vault: &Vault,
+    dai: Option<Wad>,
+    price: Wad,
+    pool: &Pool,
+    limits: &VatLimits,
+    unwind: Unwind,
+    flash_fee: Wad,
+) -> Result<ClosePlan, Box<dyn Error>> {
+    let debt_repaid = dai.unwrap_or(vault.debt);
+    if debt_repaid.is_zero() {
+        return Err(Box::new(PlannerError(String::from(
+            "there is no debt to repay",
+        ))));
+    }
+    // the debt left must not be dust
+    vault.payback(debt_repaid, limits)?;
+    let decimals = vault.token.decimals;
+    let mid_price = pool.get_price(decimals)?;
+    let mut plan = ClosePlan {
+        unwind,
+        debt_repaid,
+        steps: Vec::new(),
+        collateral_sold: Wad::zero(),
+        fees: Wad::zero(),
+        slippage: Wad::zero(),
+        flash_loan_fee: Wad::zero(),
+        vault: vault.clone(),
+    };
+    let mut pool = pool.clone();
+    let mut dai_received = Wad::zero();
+    match unwind {
+        Unwind::FlashLoan => {
+            plan.flash_loan_fee = debt_repaid.checked_mul(flash_fee)?;
+            let loan = debt_repaid.checked_add(plan.flash_loan_fee)?;
+            let quote = pool.buy_dai(loan.0)?;
+            let collateral_sold = Wad::from_decimals(quote.amount_in, decimals)?;
+            if collateral_sold > vault.collateral {
+                return Err(Box::new(PlannerError(format!(
+                    "repaying {:.2} dai takes {:.4} collateral, the vault only has {:.4}",
+                    debt_repaid, collateral_sold, vault.collateral
+                ))));
+            }
+            plan.vault =
+                vault
+                    .payback(debt_repaid, limits)?
+                    .withdraw(collateral_sold, price, limits)?;
+            plan.collateral_sold = collateral_sold;
+            dai_received = loan;
+            plan.steps.push(UnwindStep {
+                collateral_sold,
+                dai_received,
+                debt: plan.vault.debt,
+                ratio: plan.vault.get_ratio(price)?,
+            });
+        }
+        Unwind::Iterative => {
+            let mut repaid = Wad::zero();
+            // DAI kept for the next round, so that no round leaves a dusty debt
+            let mut held = Wad::zero();
+            while repaid < debt_repaid {
+                if plan.steps.len() == MAX_UNWIND_STEPS {
+                    return Err(Box::new(PlannerError(format!(
+                        "still {:.2} dai to repay after {} rounds, a flash loan is needed",
+                        debt_repaid.checked_sub(repaid)?,
+                        MAX_UNWIND_STEPS
+                    ))));
+                }
+                let free = get_free_collateral(&plan.vault, price, limits)?;
+                if free.is_zero() {
+                    return Err(Box::new(PlannerError(format!(
+                        "no collateral can be withdrawn at {}%, a flash loan is needed",
+                        limits.liquidation_ratio.to_percent()
+                    ))));
+                }
+                let missing = debt_repaid.checked_sub(repaid)?.checked_sub(held)?;
+                let needed = pool.buy_dai(missing.0)?;
+                let quote = match needed.amount_in <= free.to_decimals(decimals)? {
+                    true => needed,
+                    false => pool.sell_collateral(free.to_decimals(decimals)?)?,
+                };
+                let collateral_sold = Wad::from_decimals(quote.amount_in, decimals)?;
+                let received = Wad(quote.amount_out);
+                let available = held.checked_add(received)?;
+                let payback = match plan.vault.debt.checked_sub(available) {
+                    Ok(debt) if !debt.is_zero() && debt < limits.dust => {
+                        plan.vault.debt.checked_sub(limits.dust)?
+                    }
+                    _ => available,
+                };
+                held = available.checked_sub(payback)?;
+                repaid = repaid.checked_add(payback)?;
+                plan.vault = plan
+                    .vault
+                    .withdraw(collateral_sold, price, limits)?
+                    .payback(payback, limits)?;
+                pool = pool.after_collateral_sale(&quote);
+                plan.collateral_sold = plan.collateral_sold.checked_add(collateral_sold)?;
+                dai_received = dai_received.checked_add(received)?;
+                plan.steps.push(UnwindStep {
+                    collateral_sold,
+                    dai_received: received,
+                    debt: plan.vault.debt,
+                    ratio: plan.vault.get_ratio(price)?,
+                });
+            }
+        }
+    }
+    // the fee is 0.3% of what is sold, the rest of the loss against the mid price is the
+    // price impact
+    let value = plan.collateral_sold.checked_mul(mid_price)?;
+    plan.fees = value.checked_mul(Wad::from_f64(0.003)?)?;
+    plan.slippage = value.saturating_sub(dai_received).saturating_sub(plan.fees);
+    Ok(plan)
+}
+
+/// A sale of DAI on the pool and the collateral it adds to the vault.
+#[derive(Debug, Clone)]
+pub struct LeverStep {
+    pub dai_sold: Wad,
+    pub collateral_bought: Wad,
+    pub debt: Wad,
+    /// At the oracle price.
+    pub ratio: Wad,
+}
+
+/// What boosting a vault to a target ratio takes at the current price.
+#[derive(Debug, Clone)]
+pub struct LeveragePlan {
+    pub leverage: Leverage,
+    /// One step for a flash loan, every round of an iterative boost.
+    pub steps: Vec<LeverStep>,
+    /// The DAI minted, the flash loan fee included.
+    pub dai_drawn: Wad,
+    pub collateral_bought: Wad,
+    /// The uniswap fee, in DAI.
+    pub fees: Wad,
+    /// The value lost to the price impact past the fee, in DAI at the pool mid price.
+    pub slippage: Wad,
+    pub flash_loan_fee: Wad,
+    /// At the oracle price.
+    pub ratio: Wad,
+    pub liquidation_price: Wad,
+    pub vault: Vault,
+}
+
+/// Plans drawing DAI from `vault` and buying collateral with it on `pool`, until the ratio
+/// at the oracle `price` comes down to `target_ratio`. The Vat checks the vault against
+/// `limits`, and a flash loan costs `flash_fee` of the amount borrowed.
+pub fn get_leverage_plan(
+    vault: &Vault,
+    target_ratio: Wad,
+    price: Wad,
+    pool: &Pool,
+    limits: &VatLimits,
+    leverage: Leverage,
+    flash_fee: Wad,
+) -> Result<LeveragePlan, Box<dyn Error>> {
+    if target_ratio < limits.liquidation_ratio {
+        return Err(Box::new(PlannerError(format!(
+            "the target ratio ({}%) is below the liquidation ratio ({}%)",
+            target_ratio.to_percent(),
+            limits.liquidation_ratio.to_percent()
+        ))));
+    }
+    if let Some(ratio) = vault.get_ratio(price)? {
+        if ratio <= target_ratio {
+            return Err(Box::new(PlannerError(format!(
+                "the ratio ({:.2}%) is already below the target, only a repay gets there",
+                ratio.to_percent()
+            ))));
+        }
+    }
+    let decimals = vault.token.decimals;
+    let mid_price = pool.get_price(decimals)?;
+    let mut plan = LeveragePlan {
+        leverage,
+        steps: Vec::new(),
+        dai_drawn: Wad::zero(),
+        collateral_bought: Wad::zero(),
+        fees: Wad::zero(),
+        slippage: Wad::zero(),
+        flash_loan_fee: Wad::zero(),
+        ratio: Wad::zero(),
+        liquidation_price: Wad::zero(),
+        vault: vault.clone(),
+    };
+    let dai_sold = match leverage {
+        Leverage::FlashLoan => {
+            // the ratio only falls as more is borrowed, the largest loan above the target is
+            // searched, from the loan that reaches it without any cost
+            let fee_factor = Wad::one().checked_add(flash_fee)?;
+            let ratio_after = |loan: Wad| -> Result<Wad, Box<dyn Error>> {
+                let bought = Wad::from_decimals(pool.sell_dai(loan.0)?.amount_out, decimals)?;
+                let debt = vault.debt.checked_add(loan.checked_mul(fee_factor)?)?;
+                vault
+                    .collateral
+                    .checked_add(bought)?
+                    .checked_mul(price)?
+                    .checked_div(debt)
+            };
+            let value = vault.collateral.checked_mul(price)?;
+            let free_value = value.checked_sub(vault.debt.checked_mul(target_ratio)?)?;
+            let (mut low, mut high) = (
+                Uint::zero(),
+                free_value
+                    .checked_div(target_ratio.checked_sub(Wad::one())?)?
+                    .0,
+            );
+            while low < high {
+                let middle = low + (high - low + Uint::one()) / 2;
+                match ratio_after(Wad(middle))? >= target_ratio {
+                    true => low = middle,
+                    false => high = middle - Uint::one(),
+                }
+            }
+            let loan = Wad(low);
+            let quote = pool.sell_dai(loan.0)?;
+            let bought = Wad::from_decimals(quote.amount_out, decimals)?;
+            plan.flash_loan_fee = loan.checked_mul(flash_fee)?;
+            plan.dai_drawn = loan.checked_add(plan.flash_loan_fee)?;
+            plan.vault = vault.deposit(bought)?.draw(plan.dai_drawn, price, limits)?;
+            plan.collateral_bought = bought;
+            plan.steps.push(LeverStep {
+                dai_sold: loan,
+                collateral_bought: bought,
+                debt: plan.vault.debt,
+                ratio: plan.vault.get_ratio(price)?.unwrap_or_default(),
+            });
+            loan
+        }
+        Leverage::Iterative => {
+            let mut pool = pool.clone();
+            // every round uses up some of the debt ceiling
+            let mut limits = limits.clone();
+            let tolerance = target_ratio.checked_add(Wad::from_percent(BOOST_TOLERANCE))?;
+            loop {
+                match plan.vault.get_ratio(price)? {
+                    Some(ratio) if ratio <= tolerance => break,
+                    _ => {}
+                }
+                if plan.steps.len() == MAX_LEVERAGE_STEPS {
+                    return Err(Box::new(PlannerError(format!(
+                        "the ratio is still above {}% after {} rounds, a flash loan is needed",
+                        tolerance.to_percent(),
+                        MAX_LEVERAGE_STEPS
+                    ))));
+                }
+                // each round draws down to the target, the collateral bought lifts it again
+                let draw = plan
+                    .vault
+                    .collateral
+                    .checked_mul(price)?
+                    .checked_div(target_ratio)?
+                    .saturating_sub(plan.vault.debt);
+                let quote = pool.sell_dai(draw.0)?;
+                let bought = Wad::from_decimals(quote.amount_out, decimals)?;
+                plan.vault = plan.vault.draw(draw, price, &limits)?.deposit(bought)?;
+                limits.available_debt = limits.available_debt.checked_sub(draw)?;
+                pool = pool.after_dai_sale(&quote);
+                plan.dai_drawn = plan.dai_drawn.checked_add(draw)?;
+                plan.collateral_bought = plan.collateral_bought.checked_add(bought)?;
+                plan.steps.push(LeverStep {
+                    dai_sold: draw,
+                    collateral_bought: bought,
+                    debt: plan.vault.debt,
+                    ratio: plan.vault.get_ratio(price)?.unwrap_or_default(),
+                });
+            }
+            plan.dai_drawn
+        }
+    };
+    // the fee is 0.3% of what is sold, the rest of the loss against the mid price is the
+    // price impact
+    plan.fees = dai_sold.checked_mul(Wad::from_f64(0.003)?)?;
+    plan.slippage = dai_sold
+        .saturating_sub(plan.collateral_bought.checked_mul(mid_price)?)
+        .saturating_sub(plan.fees);
+    plan.ratio = plan.vault.get_ratio(price)?.unwrap_or_default();
+    plan.liquidation_price = plan.vault.get_liquidation_price(limits.liquidation_ratio)?;
+    Ok(plan)
+}
+
+/// The collateral the Vat lets the vault withdraw at `price`, rounded down.
+fn get_free_collateral(
+    vault: &Vault,
+    price: Wad,
+    limits: &VatLimits,
+) -> Result<Wad, Box<dyn Error>> {
+    let locked = vault
+        .debt
+        .checked_mul(limits.liquidation_ratio)?
+        .checked_div(price)?
+        .checked_add(Wad(Uint::one()))?;
+    Ok(vault.collateral.saturating_sub(locked))
+}
+
+#[derive(Debug, Clone)]
+pub struct PlannerError(pub String);
+
+impl fmt::Display for PlannerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for PlannerError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{limits, vault, wad};
+
+    /// Deep enough for the price impact to stay small, at 100.
+    fn pool() -> Pool {
+        Pool {
+            dai_reserve: wad(10_000_000.0).0,
+            col_reserve: wad(100_000.0).0,
+        }
+    }
+
+    #[test]
+    fn flash_loan_close_repays_everything_at_once() {
+        let (vault, price) = (vault(), wad(100.0));
+        let plan = get_close_plan(
+            &vault,
+            None,
+            price,
+            &pool(),
+            &limits(),
+            Unwind::FlashLoan,
+            wad(0.0009),
+        )
+        .unwrap();
+        assert_eq!(plan.steps.len(), 1);
+        assert!(plan.vault.debt.is_zero());
+        assert_eq!(plan.debt_repaid, vault.debt);
+        assert_eq!(plan.flash_loan_fee, wad(9.0));
+        // 10009 dai bought at about 100, less the fee
+        let sold = plan.collateral_sold.to_f64();
+        assert!(sold > 100.09 / 0.997 && sold < 100.09 / 0.997 * 1.002);
+        assert_eq!(
+            plan.vault.collateral,
+            vault.collateral.checked_sub(plan.collateral_sold).unwrap()
+        );
+        assert!((plan.fees.to_f64() - sold * 100.0 * 0.003).abs() < 1e-6);
+        let cost = plan.get_cost().unwrap().to_f64();
+        assert!(cost > 30.0 + 9.0 && cost < 60.0);
+    }
+
+    #[test]
+    fn iterative_close_stays_above_the_liquidation_ratio() {
+        let (vault, price) = (vault(), wad(100.0));
+        let plan = get_close_plan(
+            &vault,
+            None,
+            price,
+            &pool(),
+            &limits(),
+            Unwind::Iterative,
+            wad(0.0009),
+        )
+        .unwrap();
+        assert!(plan.steps.len() > 1);
+        assert!(plan.vault.debt.is_zero());
+        assert!(plan.flash_loan_fee.is_zero());
+        for step in &plan.steps {
+            if let Some(ratio) = step.ratio {
+                assert!(ratio >= Wad::from_percent(150));
+            }
+        }
+        // the first round can only free the collateral above 150%
+        assert!(plan.steps[0].collateral_sold <= wad(30.0));
+    }
+
+    #[test]
+    fn close_plan_refuses_dust_and_missing_debt() {
+        let (vault, price) = (vault(), wad(100.0));
+        let plan = |vault: &Vault, dai| {
+            get_close_plan(
+                vault,
+                dai,
+                price,
+                &pool(),
+                &limits(),
+                Unwind::FlashLoan,
+                wad(0.0),
+            )
+        };
+        let partial = plan(&vault, Some(wad(4000.0))).unwrap();
+        assert_eq!(partial.vault.debt, wad(6000.0));
+        assert!(plan(&vault, Some(wad(6000.0))).is_err());
+        let mut no_debt = vault.clone();
+        no_debt.debt = Wad::zero();
+        assert!(plan(&no_debt, None).is_err());
+    }
+
+    #[test]
+    fn iterative_close_needs_free_collateral() {
+        let mut vault = vault();
+        vault.collateral = wad(150.0);
+        let plan = get_close_plan(
+            &vault,
+            None,
+            wad(100.0),
+            &pool(),
+            &limits(),
+            Unwind::Iterative,
+            wad(0.0),
+        );
+        assert!(plan.is_err());
+        assert!(get_close_plan(
+            &vault,
+            None,
+            wad(100.0),
+            &pool(),
+            &limits(),
+            Unwind::FlashLoan,
+            wad(0.0),
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn flash_loan_leverage_lands_on_the_target_ratio() {
+        let (vault, price) = (vault(), wad(100.0));
+        let target = Wad::from_percent(160);
+        let plan = get_leverage_plan(
+            &vault,
+            target,
+            price,
+            &pool(),
+            &limits(),
+            Leverage::FlashLoan,
+            wad(0.0009),
+        )
+        .unwrap();
+        assert_eq!(plan.steps.len(), 1);
+        assert!(plan.ratio >= target);
+        assert!(plan.ratio.to_f64() - target.to_f64() < 1e-6);
+        assert_eq!(
+            plan.dai_drawn,
+            plan.steps[0]
+                .dai_sold
+                .checked_add(plan.flash_loan_fee)
+                .unwrap()
+        );
+        assert_eq!(
+            plan.vault.collateral,
+            vault
+                .collateral
+                .checked_add(plan.collateral_bought)
+                .unwrap()
+        );
+        // debt * 150% / collateral
+        let liquidation = plan.vault.debt.to_f64() * 1.5 / plan.vault.collateral.to_f64();
+        assert!((plan.liquidation_price.to_f64() - liquidation).abs() < 1e-6);
+    }
+
+    #[test]
+    fn iterative_leverage_stops_within_the_tolerance() {
+        let (vault, price) = (vault(), wad(100.0));
+        let target = Wad::from_percent(160);
+        let plan = get_leverage_plan(
+            &vault,
+            target,
+            price,
+            &pool(),
+            &limits(),
+            Leverage::Iterative,
+            wad(0.0009),
+        )
+        .unwrap();
+        assert!(plan.steps.len() > 1);
+        assert!(plan.flash_loan_fee.is_zero());
+        assert!(plan.ratio > target);
+        assert!(plan.ratio <= Wad::from_percent(160 + BOOST_TOLERANCE));
+        // each round lowers the ratio
+        for pair in plan.steps.windows(2) {
+            assert!(pair[1].ratio < pair[0].ratio);
+        }
+    }
+
+    #[test]
+    fn leverage_plan_needs_a_target_between_the_liquidation_and_the_current_ratio() {
+        let (vault, price) = (vault(), wad(100.0));
+        let plan = |target| {
+            get_leverage_plan(
+                &vault,
+                Wad::from_percent(target),
+                price,
+                &pool(),
+                &limits(),
+                Leverage::FlashLoan,
+                wad(0.0),
+            )
+        };
+        assert!(plan(140).is_err());
+        assert!(plan(180).is_err());
+        assert!(plan(200).is_err());
+        assert!(plan(150).is_ok());
+    }
+}
@@ -0,0 +1,58 @@
+use super::blockchain::{is_call_failure, BlockchainReader};
+use super::ds_proxy::DsProxy;
+use ethabi::{Address, Contract, Token};
+use std::error::Error;
+
+const PROXY_REGISTRY_ADDRESS: &str = "4678f0a6958e4d2bc4f1baf7bc52e8f3564f3fe4";
+
+pub struct ProxyRegistry<'a> {
+    blockchain_reader: &'a dyn BlockchainReader,
+    proxy_registry_address: Address,
+    proxy_registry_contract: Contract,
+}
+impl<'a> ProxyRegistry<'a> {
+    pub fn new(blockchain_reader: &'a (dyn BlockchainReader + 'a)) -> Result<Self, Box<dyn Error>> {
+        let proxy_registry_address: Address = PROXY_REGISTRY_ADDRESS.parse()?;
+        let proxy_registry_abi: &[u8] = include_bytes!("abi/proxy_registry.abi");
+        let proxy_registry_contract = Contract::load(proxy_registry_abi)?;
+        Ok(Self {
+            blockchain_reader,
+            proxy_registry_address,
+            proxy_registry_contract,
+        })
+    }
+
+    /// The proxy built for `owner`, `None` if the wallet never built one.
+    pub async fn get_proxy(&self, owner: &Address) -> Result<Option<Address>, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.proxy_registry_contract,
+                &self.proxy_registry_address,
+                "proxies",
+                &[Token::Address(*owner)],
+            )
+            .await?;
+
+        let proxy = tokens[0].clone().to_address().unwrap();
+        match proxy.is_zero() {
+            true => Ok(None),
+            false => Ok(Some(proxy)),
+        }
+    }
+
+    /// The wallet behind `proxy`, `None` if the address is not a registered DSProxy.
+    pub async fn get_owner(&self, proxy: &Address) -> Result<Option<Address>, Box<dyn Error>> {
+        let ds_proxy = DsProxy::new(self.blockchain_reader, *proxy)?;
+        let owner = match ds_proxy.get_owner().await {
+            Ok(owner) => owner,
+            // a wallet or a contract without `owner`
+            Err(e) if is_call_failure(&*e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match self.get_proxy(&owner).await? {
+            Some(owner_proxy) if owner_proxy == *proxy => Ok(Some(owner)),
+            _ => Ok(None),
+        }
+    }
+}
@@ -1,10 +1,75 @@
-use super::blockchain::BlockchainReader;
+use super::blockchain::{is_revert, BlockchainReader};
+use super::cdp_manager::{CdpManager, MCD_DEPLOYMENT_BLOCK};
 use super::erc_20::Erc20Token;
-use super::vault::Vault;
-use ethabi::{Address, Contract, Token, Uint};
+use super::gem_join::GemJoin;
+use super::ilk_registry::IlkRegistry;
+use super::math::{Rad, Ray, Wad};
+use super::vat::Vat;
+use super::vault::{VatLimits, Vault};
+use ethabi::{decode, Address, Contract, Hash, ParamType, Token, Uint};
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
+use web3::types::{BlockNumber, FilterBuilder};
 
 const SAVER_ADDRESS: &str = "c45d4f6b6bf41b6edaa58b01c4298b8d9078269a";
+
+/// The ilk parameters as seen by the Saver, in the Vat units.
+#[derive(Debug, Clone)]
+pub struct IlkInfo {
+    pub art: Wad,
+    pub rate: Ray,
+    pub spot: Ray,
+    pub line: Rad,
+    pub dust: Rad,
+    /// The liquidation ratio.
+    pub mat: Ray,
+    pub par: Ray,
+}
+
+impl IlkInfo {
+    pub fn get_vat_limits(&self) -> Result<VatLimits, Box<dyn Error>> {
+        let ilk_debt = Rad::from_product(self.art, self.rate)?;
+        Ok(VatLimits {
+            liquidation_ratio: self.mat.to_wad(),
+            dust: self.dust.to_wad(),
+            available_debt: self.line.saturating_sub(ilk_debt).to_wad(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingsAction {
+    Subscribed,
+    Updated,
+    Unsubscribed,
+}
+
+impl fmt::Display for SettingsAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self {
+            SettingsAction::Subscribed => "subscribed",
+            SettingsAction::Updated => "updated",
+            SettingsAction::Unsubscribed => "unsubscribed",
+        };
+        f.pad(action)
+    }
+}
+
+/// A change of the automation settings of a vault, the ratios are zero once unsubscribed.
+#[derive(Debug, Clone)]
+pub struct SettingsChange {
+    pub action: SettingsAction,
+    pub block_number: u64,
+    pub timestamp: u64,
+    pub transaction_hash: Hash,
+    pub min_ratio: Wad,
+    pub max_ratio: Wad,
+    pub repay_ratio: Wad,
+    pub boost_ratio: Wad,
+    pub boost_enabled: bool,
+}
+
 pub struct Saver<'a> {
     blockchain_reader: &'a dyn BlockchainReader,
     saver_address: Address,
@@ -25,13 +90,24 @@ impl<'a> Saver<'a> {
     }
 
     pub async fn get_vault(&self, vault_id: Uint) -> Result<Vault, Box<dyn Error>> {
+        self.get_vault_at(vault_id, BlockNumber::Latest).await
+    }
+
+    /// The vault as of `block`, its collateral and debt read from the Vat and its automation
+    /// settings from the Saver, all zero when it is not subscribed.
+    pub async fn get_vault_at(
+        &self,
+        vault_id: Uint,
+        block: BlockNumber,
+    ) -> Result<Vault, Box<dyn Error>> {
         let tokens = self
             .blockchain_reader
-            .call_function(
+            .call_function_at(
                 &self.saver_contract,
                 &self.saver_address,
                 "getSubscribedInfo",
                 &[Token::Uint(vault_id)],
+                block,
             )
             .await?;
 
@@ -40,9 +116,19 @@ impl<'a> Saver<'a> {
         let max_ratio = &tokens[2].clone().to_uint();
         let repay_ratio = &tokens[3].clone().to_uint();
         let boost_ratio = &tokens[4].clone().to_uint();
-        let owner = &tokens[5].clone().to_address();
-        let collateral = &tokens[6].clone().to_uint();
-        let debt = &tokens[7].clone().to_uint();
+
+        let cdp_manager = CdpManager::new(self.blockchain_reader)?;
+        let ilk_registry = IlkRegistry::new(self.blockchain_reader)?;
+        let vat = Vat::new(self.blockchain_reader)?;
+        let ilk_id = cdp_manager.get_ilk_id(vault_id).await?;
+        let urn = cdp_manager.get_urn(vault_id).await?;
+        // the vat always counts collateral with 18 decimals
+        let (collateral, debt) = vat.get_vault_at(&ilk_id, &urn, block).await?;
+        let owner = cdp_manager.get_owner(vault_id).await?;
+        let gem_join_address = ilk_registry.get_join_address(&ilk_id).await?;
+        let gem_join = GemJoin::new(self.blockchain_reader, gem_join_address)?;
+        let gem_address = gem_join.get_gem_address().await?;
+        let token = Erc20Token::load(self.blockchain_reader, gem_address).await?;
 
         let vault = Vault {
             subscribed: subscribed.unwrap(),
@@ -50,20 +136,219 @@ impl<'a> Saver<'a> {
             //max_ratio: Uint::from(220) * Uint::exp10(16),
             //repay_ratio: Uint::from(180) * Uint::exp10(16),
             //boost_ratio: Uint::from(180) * Uint::exp10(16),
-            min_ratio: min_ratio.unwrap(),
-            max_ratio: max_ratio.unwrap(),
-            repay_ratio: repay_ratio.unwrap(),
-            boost_ratio: boost_ratio.unwrap(),
-            owner: owner.unwrap(),
-            collateral: collateral.unwrap(),
-            debt: debt.unwrap(),
-            token: Erc20Token {
-                name: "Wrapped Ether".to_string(),
-                symbol: "WETH".to_string(),
-                decimals: 18,
-                address: "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".parse()?,
-            },
+            min_ratio: Wad(min_ratio.unwrap()),
+            max_ratio: Wad(max_ratio.unwrap()),
+            repay_ratio: Wad(repay_ratio.unwrap()),
+            boost_ratio: Wad(boost_ratio.unwrap()),
+            owner,
+            collateral,
+            debt,
+            token,
+            close_rules: Vec::new(),
+            dai: Wad::zero(),
         };
         Ok(vault)
     }
+
+    /// The ids of the vaults subscribed at `page`, the last page holding less than `per_page`.
+    pub async fn get_subscribed_ids_by_page(
+        &self,
+        page: usize,
+        per_page: usize,
+    ) -> Result<Vec<Uint>, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.saver_contract,
+                &self.saver_address,
+                "getSubscribersByPage",
+                &[
+                    Token::Uint(Uint::from(page)),
+                    Token::Uint(Uint::from(per_page)),
+                ],
+            )
+            .await?;
+
+        let holders = tokens[0].clone().to_array().unwrap();
+        let mut vault_ids = Vec::with_capacity(holders.len());
+        for holder in holders {
+            if let Token::Tuple(holder) = holder {
+                let vault_id = holder[5].clone().to_uint().unwrap();
+                // the contract pads the last page with empty holders
+                if !vault_id.is_zero() {
+                    vault_ids.push(vault_id);
+                }
+            }
+        }
+        Ok(vault_ids)
+    }
+
+    pub async fn get_vaults_by_page(
+        &self,
+        page: usize,
+        per_page: usize,
+    ) -> Result<Vec<Vault>, Box<dyn Error>> {
+        let vault_ids = self.get_subscribed_ids_by_page(page, per_page).await?;
+        let mut vaults = Vec::with_capacity(vault_ids.len());
+        for vault_id in vault_ids {
+            vaults.push(self.get_vault(vault_id).await?);
+        }
+        Ok(vaults)
+    }
+
+    /// The ids of every subscribed vault, read `per_page` at a time.
+    pub async fn get_subscribed_ids(&self, per_page: usize) -> Result<Vec<Uint>, Box<dyn Error>> {
+        let mut vault_ids = Vec::new();
+        let mut page = 0;
+        loop {
+            // reading past the last subscriber reverts
+            let page_ids = match self.get_subscribed_ids_by_page(page, per_page).await {
+                Ok(page_ids) => page_ids,
+                Err(e) if page > 0 && is_revert(&*e) => break,
+                Err(e) => return Err(e),
+            };
+            let last_page = page_ids.len() < per_page;
+            vault_ids.extend(page_ids);
+            if last_page {
+                break;
+            }
+            page += 1;
+        }
+        Ok(vault_ids)
+    }
+
+    /// The lowest min ratio the Saver accepts for `ilk_id`.
+    pub async fn get_min_limit(&self, ilk_id: &[u8]) -> Result<Wad, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.saver_contract,
+                &self.saver_address,
+                "minLimits",
+                &[Token::FixedBytes(ilk_id.to_vec())],
+            )
+            .await?;
+
+        let min_limit = tokens[0].clone().to_uint();
+        Ok(Wad(min_limit.unwrap()))
+    }
+
+    pub async fn get_ilk_info(
+        &self,
+        ilk_id: &[u8],
+        vault_id: Uint,
+    ) -> Result<IlkInfo, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.saver_contract,
+                &self.saver_address,
+                "getIlkInfo",
+                &[Token::FixedBytes(ilk_id.to_vec()), Token::Uint(vault_id)],
+            )
+            .await?;
+
+        let ilk_info = IlkInfo {
+            art: Wad(tokens[1].clone().to_uint().unwrap()),
+            rate: Ray(tokens[2].clone().to_uint().unwrap()),
+            spot: Ray(tokens[3].clone().to_uint().unwrap()),
+            line: Rad(tokens[4].clone().to_uint().unwrap()),
+            dust: Rad(tokens[5].clone().to_uint().unwrap()),
+            mat: Ray(tokens[6].clone().to_uint().unwrap()),
+            par: Ray(tokens[7].clone().to_uint().unwrap()),
+        };
+        Ok(ilk_info)
+    }
+
+    /// Every subscription, update and unsubscription of the vault, oldest first.
+    pub async fn get_settings_history(
+        &self,
+        vault_id: Uint,
+    ) -> Result<Vec<SettingsChange>, Box<dyn Error>> {
+        let subscribed = self.saver_contract.event("Subscribed")?.signature();
+        let updated = self.saver_contract.event("Updated")?.signature();
+        let unsubscribed = self.saver_contract.event("Unsubscribed")?.signature();
+        let param_updates = self.saver_contract.event("ParamUpdates")?.signature();
+        // the vault id is not indexed, every event of the Saver has to be read
+        let filter = FilterBuilder::default()
+            .address(vec![self.saver_address])
+            .topics(
+                Some(vec![subscribed, updated, unsubscribed, param_updates]),
+                None,
+                None,
+                None,
+            );
+        let logs = self
+            .blockchain_reader
+            .get_logs_by_range(&filter, MCD_DEPLOYMENT_BLOCK, BlockNumber::Latest)
+            .await?;
+
+        let mut history: Vec<SettingsChange> = Vec::new();
+        let mut timestamps: HashMap<u64, u64> = HashMap::new();
+        for log in logs {
+            let event = log.topics[0];
+            let tokens = match event == param_updates {
+                true => decode(
+                    &[
+                        ParamType::Uint(256),
+                        ParamType::Uint(128),
+                        ParamType::Uint(128),
+                        ParamType::Uint(128),
+                        ParamType::Uint(128),
+                        ParamType::Bool,
+                    ],
+                    &log.data.0,
+                )?,
+                false => decode(&[ParamType::Uint(256)], &log.data.0)?,
+            };
+            if tokens[0].clone().to_uint().unwrap() != vault_id {
+                continue;
+            }
+            let transaction_hash = log.transaction_hash.unwrap_or_default();
+            if event == param_updates {
+                // emitted right after Subscribed or Updated, in the same transaction
+                if let Some(change) = history
+                    .iter_mut()
+                    .rev()
+                    .find(|change| change.transaction_hash == transaction_hash)
+                {
+                    change.min_ratio = Wad(tokens[1].clone().to_uint().unwrap());
+                    change.max_ratio = Wad(tokens[2].clone().to_uint().unwrap());
+                    change.boost_ratio = Wad(tokens[3].clone().to_uint().unwrap());
+                    change.repay_ratio = Wad(tokens[4].clone().to_uint().unwrap());
+                    change.boost_enabled = tokens[5].clone().to_bool().unwrap();
+                }
+                continue;
+            }
+            let action = match event {
+                e if e == subscribed => SettingsAction::Subscribed,
+                e if e == updated => SettingsAction::Updated,
+                _ => SettingsAction::Unsubscribed,
+            };
+            let block_number = log.block_number.unwrap_or_default().as_u64();
+            let timestamp = match timestamps.get(&block_number) {
+                Some(timestamp) => *timestamp,
+                None => {
+                    let timestamp = self
+                        .blockchain_reader
+                        .get_block_timestamp(block_number)
+                        .await?;
+                    timestamps.insert(block_number, timestamp);
+                    timestamp
+                }
+            };
+            history.push(SettingsChange {
+                action,
+                block_number,
+                timestamp,
+                transaction_hash,
+                min_ratio: Wad::zero(),
+                max_ratio: Wad::zero(),
+                repay_ratio: Wad::zero(),
+                boost_ratio: Wad::zero(),
+                boost_enabled: false,
+            });
+        }
+        Ok(history)
+    }
 }
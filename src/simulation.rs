@@ -0,0 +1,561 @@
+use super::math::Wad;
+use super::vault::{
+    AutomationAction, AutomationStep, Close, Equity, Friction, Liquidation, LiquidationTerms, Vault,
+};
+use ethabi::Uint;
+use std::error::Error;
+use std::fmt;
+
+/// A price of the series, with its time when known.
+#[derive(Debug, Clone)]
+pub struct Tick {
+    pub timestamp: Option<u64>,
+    pub price: Wad,
+}
+
+/// The vault after the automation reacted to one tick.
+#[derive(Debug, Clone)]
+pub struct EquityPoint {
+    pub timestamp: Option<u64>,
+    pub price: Wad,
+    /// The price the automation and the keepers acted on.
+    pub oracle_price: Wad,
+    pub equity: Equity,
+    pub ratio: Option<Wad>,
+    /// The boosts and repays triggered by this tick.
+    pub steps: Vec<AutomationStep>,
+}
+
+/// Reads a CSV whose first column is the time (a unix timestamp or a `YYYY-MM-DD` date,
+/// anything after the date is ignored) and the second the price. A first line that does not
+/// parse is taken as a header.
+pub fn read_ticks(csv: &str) -> Result<Vec<Tick>, Box<dyn Error>> {
+    let mut ticks = Vec::new();
+    for (index, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut columns = line
+            .split(',')
+            .map(|column| column.trim().trim_matches('"'));
+        let timestamp = columns.next().and_then(parse_time);
+        let price = columns.next().and_then(|price| price.parse::<f64>().ok());
+        match (timestamp, price) {
+            (Some(timestamp), Some(price)) => ticks.push(Tick {
+                timestamp: Some(timestamp),
+                price: Wad::from_f64(price)?,
+            }),
+            _ if index == 0 => continue,
+            _ => {
+                return Err(Box::new(SimulationError(format!(
+                    "line {}: expected a time and a price, got \"{}\"",
+                    index + 1,
+                    line
+                ))))
+            }
+        }
+    }
+    Ok(ticks)
+}
+
+fn parse_time(time: &str) -> Option<u64> {
+    if let Ok(timestamp) = time.parse::<u64>() {
+        return Some(timestamp);
+    }
+    let date = time.get(..10)?;
+    let mut parts = date.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some((days * 86400) as u64)
+}
+
+/// A vault replayed along a price series.
+#[derive(Debug, Clone)]
+pub struct PathResult {
+    pub points: Vec<EquityPoint>,
+    pub vault: Vault,
+    /// The equity had the vault jumped straight from the first to the last price.
+    pub direct_equity: Equity,
+    pub boosts: usize,
+    pub repays: usize,
+    /// The lowest ratio seen along the path, `None` when there never was any debt.
+    pub lowest_ratio: Option<Wad>,
+    /// Once liquidated, the vault has no debt left and the automation stops.
+    pub liquidation: Option<Liquidation>,
+    /// Once closed by a stop-loss or a take-profit, the automation stops too.
+    pub close: Option<Close>,
+}
+
+impl PathResult {
+    pub fn get_final_equity(&self) -> Equity {
+        match self.points.last() {
+            Some(point) => point.equity,
+            None => Equity::Positive(Wad::zero()),
+        }
+    }
+
+    /// The share of the equity lost to the path compared to the direct jump, a round trip
+    /// costs the automation the friction of every boost and repay it triggered.
+    pub fn get_volatility_drag(&self) -> f64 {
+        let direct = self.direct_equity.to_f64();
+        if direct == 0.0 {
+            return 0.0;
+        }
+        1.0 - self.get_final_equity().to_f64() / direct
+    }
+}
+
+/// Replays `vault` along `ticks`, applying the boost and repay rules at each of them and
+/// liquidating it under `terms`. With a `delay` (3600 seconds for the OSM), the automation
+/// and the keepers see the price `delay` seconds earlier, interpolated between the ticks
+/// around it, while the trades happen at the current one, and a crash can get the vault
+/// liquidated before a repay lands.
+pub fn simulate_path(
+    vault: &Vault,
+    ticks: &[Tick],
+    friction: &Friction,
+    delay: u64,
+    terms: &LiquidationTerms,
+) -> Result<PathResult, Box<dyn Error>> {
+    let (first, last) = match (ticks.first(), ticks.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            return Err(Box::new(SimulationError(String::from(
+                "no price to simulate",
+            ))))
+        }
+    };
+    let direct = vault.trace_vault(first.price, friction)?.vault;
+    let direct_equity = direct
+        .trace_vault(last.price, friction)?
+        .vault
+        .get_equity(last.price)?;
+
+    let mut result = PathResult {
+        points: Vec::with_capacity(ticks.len()),
+        vault: vault.clone(),
+        direct_equity,
+        boosts: 0,
+        repays: 0,
+        lowest_ratio: None,
+        liquidation: None,
+        close: None,
+    };
+    let mut lagged = 0;
+    for tick in ticks {
+        let oracle_price = match (delay, tick.timestamp) {
+            (0, _) => tick.price,
+            (_, Some(timestamp)) => {
+                // the last tick at least `delay` old, or the first one
+                while let Some(next) = ticks.get(lagged + 1) {
+                    match next.timestamp {
+                        Some(time) if time + delay <= timestamp => lagged += 1,
+                        _ => break,
+                    }
+                }
+                get_lagged_price(
+                    &ticks[lagged],
+                    ticks.get(lagged + 1),
+                    timestamp.saturating_sub(delay),
+                )?
+            }
+            (_, None) => {
+                return Err(Box::new(SimulationError(String::from(
+                    "a delayed oracle needs the time of every price",
+                ))))
+            }
+        };
+        let mut steps = Vec::new();
+        if result.liquidation.is_none() && result.close.is_none() {
+            // behind the oracle, the keepers act on the same poke as the automation
+            let liquidatable =
+                delay > 0 && result.vault.is_liquidatable(oracle_price, terms.ratio)?;
+            if !liquidatable {
+                // a stalled boost leaves the vault as it is until the price moves again
+                let trace = match delay {
+                    0 => result.vault.trace_vault(tick.price, friction)?,
+                    _ => result
+                        .vault
+                        .trace_vault_delayed(oracle_price, tick.price, friction)?,
+                };
+                for step in &trace.steps {
+                    match step.action {
+                        AutomationAction::Boost => result.boosts += 1,
+                        AutomationAction::Repay => result.repays += 1,
+                    }
+                }
+                result.vault = trace.vault;
+                result.close = trace.close;
+                steps = trace.steps;
+            }
+            if result.vault.is_liquidatable(oracle_price, terms.ratio)? {
+                let (vault, liquidation) =
+                    result.vault.liquidate(oracle_price, tick.price, terms)?;
+                result.vault = vault;
+                result.liquidation = Some(liquidation);
+            }
+        }
+        let ratio = result.vault.get_ratio(tick.price)?;
+        result.lowest_ratio = match (result.lowest_ratio, ratio) {
+            (Some(lowest), Some(ratio)) if ratio < lowest => Some(ratio),
+            (None, ratio) => ratio,
+            (lowest, _) => lowest,
+        };
+        result.points.push(EquityPoint {
+            timestamp: tick.timestamp,
+            price: tick.price,
+            oracle_price,
+            equity: result.vault.get_equity(tick.price)?,
+            ratio,
+            steps,
+        });
+    }
+    Ok(result)
+}
+
+/// The price at `time`, on the line between `before` and `after`, the first price when `time`
+/// comes before it.
+fn get_lagged_price(before: &Tick, after: Option<&Tick>, time: u64) -> Result<Wad, Box<dyn Error>> {
+    let (from, to, after) = match (before.timestamp, after) {
+        (Some(from), Some(after)) => match after.timestamp {
+            Some(to) if from <= time && time < to => (from, to, after),
+            _ => return Ok(before.price),
+        },
+        _ => return Ok(before.price),
+    };
+    let elapsed = Uint::from(time - from);
+    let span = Uint::from(to - from);
+    match after.price >= before.price {
+        true => before.price.checked_add(Wad(
+            after.price.checked_sub(before.price)?.0 * elapsed / span
+        )),
+        false => before.price.checked_sub(Wad(
+            before.price.checked_sub(after.price)?.0 * elapsed / span
+        )),
+    }
+}
+
+/// SplitMix64, small and good enough to draw prices, the same seed gives the same paths.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in ]0, 1].
+    pub fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, with the Box-Muller transform.
+    pub fn next_normal(&mut self) -> f64 {
+        let u1 = self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+/// Geometric Brownian motion paths, `drift` and `volatility` are annual.
+#[derive(Debug, Clone)]
+pub struct MonteCarlo {
+    pub drift: f64,
+    pub volatility: f64,
+    pub days: f64,
+    /// Number of prices drawn along each path.
+    pub steps: usize,
+    pub paths: usize,
+    pub seed: u64,
+}
+
+/// The distribution of the final equity over every path that ran to the end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonteCarloReport {
+    /// Sorted, in DAI, negative when the vault ended underwater.
+    pub equities: Vec<f64>,
+    /// Share of the paths where the vault got liquidated.
+    pub liquidation_probability: f64,
+    /// Mean number of boosts and repays per path.
+    pub average_actions: f64,
+    /// Mean penalty lost per path, liquidated or not, in DAI.
+    pub average_penalty_loss: f64,
+    /// Share of the paths where a stop-loss or a take-profit closed the vault.
+    pub close_probability: f64,
+    /// Paths that could not be simulated, left out of every figure above.
+    pub failed_paths: usize,
+    /// The same paths with boosts and repays only, `None` when the vault has no close rule.
+    pub without_rules: Option<Box<MonteCarloReport>>,
+}
+
+impl MonteCarlo {
+    /// The prices drawn after `start_price`, timed in seconds from it.
+    pub fn get_path(&self, rng: &mut Rng, start_price: f64) -> Result<Vec<Tick>, Box<dyn Error>> {
+        let dt = self.days / 365.0 / self.steps as f64;
+        let seconds = self.days * 86400.0 / self.steps as f64;
+        let drift = (self.drift - self.volatility * self.volatility / 2.0) * dt;
+        let diffusion = self.volatility * dt.sqrt();
+        let mut price = start_price;
+        let mut ticks = Vec::with_capacity(self.steps);
+        for i in 1..=self.steps {
+            price *= (drift + diffusion * rng.next_normal()).exp();
+            ticks.push(Tick {
+                timestamp: Some((i as f64 * seconds).round() as u64),
+                price: Wad::from_f64(price)?,
+            });
+        }
+        Ok(ticks)
+    }
+
+    /// Runs `vault` along every path, starting at `start_price`, see `simulate_path` for
+    /// `delay` and `terms`.
+    pub fn run(
+        &self,
+        vault: &Vault,
+        start_price: Wad,
+        friction: &Friction,
+        delay: u64,
+        terms: &LiquidationTerms,
+    ) -> Result<MonteCarloReport, Box<dyn Error>> {
+        if self.paths == 0 || self.steps == 0 {
+            return Err(Box::new(SimulationError(String::from(
+                "at least one path of one step is needed",
+            ))));
+        }
+        let mut rng = Rng::new(self.seed);
+        let mut equities = Vec::with_capacity(self.paths);
+        let mut liquidations = 0;
+        let mut actions = 0;
+        let mut penalty_loss = 0.0;
+        let mut closes = 0;
+        let mut failed_paths = 0;
+        for _ in 0..self.paths {
+            let mut ticks = vec![Tick {
+                timestamp: Some(0),
+                price: start_price,
+            }];
+            // a path that overflows or cannot be traced does not void the others
+            let result = match self.get_path(&mut rng, start_price.to_f64()) {
+                Ok(mut path) => {
+                    ticks.append(&mut path);
+                    simulate_path(vault, &ticks, friction, delay, terms)
+                }
+                Err(e) => Err(e),
+            };
+            let result = match result {
+                Ok(result) => result,
+                Err(_) => {
+                    failed_paths += 1;
+                    continue;
+                }
+            };
+            if let Some(liquidation) = &result.liquidation {
+                liquidations += 1;
+                penalty_loss += liquidation.penalty_loss.to_f64();
+            }
+            if result.close.is_some() {
+                closes += 1;
+            }
+            actions += result.boosts + result.repays;
+            equities.push(result.get_final_equity().to_f64());
+        }
+        if equities.is_empty() {
+            return Err(Box::new(SimulationError(format!(
+                "the {} paths failed",
+                self.paths
+            ))));
+        }
+        equities.sort_by(f64::total_cmp);
+        let simulated = equities.len() as f64;
+        // the same seed draws the same paths
+        let without_rules = match vault.close_rules.is_empty() {
+            true => None,
+            false => {
+                let mut vault = vault.clone();
+                vault.close_rules.clear();
+                Some(Box::new(self.run(
+                    &vault,
+                    start_price,
+                    friction,
+                    delay,
+                    terms,
+                )?))
+            }
+        };
+        Ok(MonteCarloReport {
+            equities,
+            liquidation_probability: liquidations as f64 / simulated,
+            average_actions: actions as f64 / simulated,
+            average_penalty_loss: penalty_loss / simulated,
+            close_probability: closes as f64 / simulated,
+            failed_paths,
+            without_rules,
+        })
+    }
+}
+
+impl MonteCarloReport {
+    pub fn get_mean(&self) -> f64 {
+        self.equities.iter().sum::<f64>() / self.equities.len() as f64
+    }
+
+    pub fn get_median(&self) -> f64 {
+        self.get_percentile(50.0)
+    }
+
+    /// Nearest rank percentile, `percentile` in %.
+    pub fn get_percentile(&self, percentile: f64) -> f64 {
+        let rank = (percentile / 100.0 * self.equities.len() as f64).ceil() as usize;
+        self.equities[rank.clamp(1, self.equities.len()) - 1]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationError(pub String);
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for SimulationError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::get_default_vault;
+
+    fn monte_carlo(seed: u64) -> MonteCarlo {
+        MonteCarlo {
+            drift: 0.0,
+            volatility: 0.8,
+            days: 30.0,
+            steps: 30,
+            paths: 20,
+            seed,
+        }
+    }
+
+    #[test]
+    fn parse_time_reads_timestamps_and_dates() {
+        assert_eq!(parse_time("1620000000"), Some(1620000000));
+        assert_eq!(parse_time("1970-01-01"), Some(0));
+        assert_eq!(parse_time("2021-05-01"), Some(1619827200));
+        assert_eq!(parse_time("2021-05-01T12:30:00Z"), Some(1619827200));
+        // leap days and the March start of the computation year
+        assert_eq!(parse_time("2020-02-29"), Some(1582934400));
+        assert_eq!(parse_time("2000-03-01"), Some(951868800));
+        assert_eq!(parse_time("2021-13-01"), None);
+        assert_eq!(parse_time("2021-05-00"), None);
+        assert_eq!(parse_time("date"), None);
+    }
+
+    #[test]
+    fn read_ticks_skips_the_header_and_blank_lines() {
+        let csv = "\"date\",\"price\"\n2021-05-01,2950.5\n\n\"2021-05-02\", \"3000\"\n";
+        let ticks = read_ticks(csv).unwrap();
+        assert_eq!(ticks.len(), 2);
+        assert_eq!(ticks[0].timestamp, Some(1619827200));
+        assert_eq!(ticks[0].price, Wad::from_f64(2950.5).unwrap());
+        assert_eq!(ticks[1].timestamp, Some(1619827200 + 86400));
+        assert_eq!(ticks[1].price, Wad::from_f64(3000.0).unwrap());
+    }
+
+    #[test]
+    fn read_ticks_rejects_bad_lines_after_the_header() {
+        let error = read_ticks("time,price\n1,100\n2\n").unwrap_err();
+        assert!(error.to_string().starts_with("line 3:"));
+        assert!(read_ticks("1,100\n2,-5\n").is_err());
+        assert!(read_ticks("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn lagged_price_is_interpolated_between_the_ticks() {
+        let tick = |timestamp, price| Tick {
+            timestamp: Some(timestamp),
+            price: Wad::from_f64(price).unwrap(),
+        };
+        let (day, next_day) = (tick(0, 100.0), tick(86400, 200.0));
+        let lagged = |time| get_lagged_price(&day, Some(&next_day), time).unwrap();
+        assert_eq!(lagged(0), Wad::from_f64(100.0).unwrap());
+        assert_eq!(lagged(21600), Wad::from_f64(125.0).unwrap());
+        let falling = get_lagged_price(&next_day, Some(&tick(172800, 100.0)), 129600).unwrap();
+        assert_eq!(falling, Wad::from_f64(150.0).unwrap());
+        // before the first tick or after the last one, the closest price
+        assert_eq!(
+            get_lagged_price(&next_day, Some(&tick(172800, 100.0)), 0).unwrap(),
+            Wad::from_f64(200.0).unwrap()
+        );
+        assert_eq!(
+            get_lagged_price(&next_day, None, 172800).unwrap(),
+            Wad::from_f64(200.0).unwrap()
+        );
+    }
+
+    #[test]
+    fn rng_is_deterministic() {
+        let draw = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..100).map(|_| rng.next_u64()).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+        let mut rng = Rng::new(7);
+        for _ in 0..1000 {
+            let value = rng.next_f64();
+            assert!(value > 0.0 && value <= 1.0);
+        }
+    }
+
+    #[test]
+    fn monte_carlo_is_deterministic_for_a_seed() {
+        let mut vault = get_default_vault().unwrap();
+        vault.min_ratio = Wad::from_percent(160);
+        vault.repay_ratio = Wad::from_percent(190);
+        vault.boost_ratio = Wad::from_percent(190);
+        vault.max_ratio = Wad::from_percent(230);
+        let terms = LiquidationTerms {
+            ratio: Wad::from_percent(150),
+            penalty: Wad::from_percent(113),
+            auction: None,
+        };
+        let price = Wad::from_f64(100.0).unwrap();
+        let friction = Friction::Constant(0.01);
+        let run = |seed| {
+            monte_carlo(seed)
+                .run(&vault, price, &friction, 0, &terms)
+                .unwrap()
+        };
+        let report = run(42);
+        assert_eq!(report, run(42));
+        assert_eq!(report.equities.len() + report.failed_paths, 20);
+        assert!(report.equities.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_ne!(report.equities, run(43).equities);
+    }
+}
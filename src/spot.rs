@@ -1,4 +1,5 @@
 use super::blockchain::BlockchainReader;
+use super::math::Ray;
 use ethabi::{Address, Contract, Token};
 use std::error::Error;
 
@@ -21,7 +22,7 @@ impl<'a> Spot<'a> {
         })
     }
 
-    pub async fn get_median_address(&self, ilk_id: &Vec<u8>) -> Result<Address, Box<dyn Error>> {
+    pub async fn get_median_address(&self, ilk_id: &[u8]) -> Result<Address, Box<dyn Error>> {
         let tokens = self
             .blockchain_reader
             .call_function(
@@ -35,4 +36,20 @@ impl<'a> Spot<'a> {
         let median_address = tokens[0].clone().to_address();
         Ok(median_address.unwrap())
     }
+
+    /// The liquidation ratio of the ilk, `mat`.
+    pub async fn get_liquidation_ratio(&self, ilk_id: &[u8]) -> Result<Ray, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.spot_contract,
+                &self.spot_address,
+                "ilks",
+                &[Token::FixedBytes(ilk_id.to_vec())],
+            )
+            .await?;
+
+        let mat = tokens[1].clone().to_uint();
+        Ok(Ray(mat.unwrap()))
+    }
 }
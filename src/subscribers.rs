@@ -0,0 +1,173 @@
+use super::math::{MathError, Wad};
+use super::vault::Vault;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Width of the ratio buckets, in %.
+const BUCKET: usize = 10;
+
+/// Aggregate view of the automated vaults sharing the same collateral.
+#[derive(Debug, Clone)]
+pub struct SubscriberStats {
+    pub vaults: Vec<Vault>,
+    pub collateral: Wad,
+    pub debt: Wad,
+    /// Number of vaults by min ratio bucket (in %).
+    pub min_ratios: BTreeMap<usize, usize>,
+    /// Number of boost enabled vaults by max ratio bucket (in %).
+    pub max_ratios: BTreeMap<usize, usize>,
+}
+
+impl SubscriberStats {
+    /// Groups `vaults` by collateral token.
+    pub fn new(vaults: Vec<Vault>) -> Result<Vec<SubscriberStats>, Box<dyn Error>> {
+        let mut groups: BTreeMap<String, Vec<Vault>> = BTreeMap::new();
+        for vault in vaults {
+            groups
+                .entry(vault.token.symbol.clone())
+                .or_default()
+                .push(vault);
+        }
+        groups
+            .into_values()
+            .map(|vaults| {
+                let mut stats = SubscriberStats {
+                    vaults: Vec::with_capacity(vaults.len()),
+                    collateral: Wad::zero(),
+                    debt: Wad::zero(),
+                    min_ratios: BTreeMap::new(),
+                    max_ratios: BTreeMap::new(),
+                };
+                for vault in vaults {
+                    stats.collateral = stats.collateral.checked_add(vault.collateral)?;
+                    stats.debt = stats.debt.checked_add(vault.debt)?;
+                    *stats
+                        .min_ratios
+                        .entry(bucket(vault.min_ratio)?)
+                        .or_insert(0) += 1;
+                    // without boost the max ratio is only a sentinel
+                    if vault.is_boost_enabled() {
+                        *stats
+                            .max_ratios
+                            .entry(bucket(vault.max_ratio)?)
+                            .or_insert(0) += 1;
+                    }
+                    stats.vaults.push(vault);
+                }
+                Ok(stats)
+            })
+            .collect()
+    }
+
+    /// The vaults whose repay triggers when the price falls to `price`.
+    pub fn get_repaid(&self, price: Wad) -> Result<Vec<&Vault>, Box<dyn Error>> {
+        let mut repaid = Vec::new();
+        for vault in self.get_active() {
+            if price < vault.get_down_price()? {
+                repaid.push(vault);
+            }
+        }
+        Ok(repaid)
+    }
+
+    /// The vaults whose boost triggers when the price rises to `price`.
+    pub fn get_boosted(&self, price: Wad) -> Result<Vec<&Vault>, Box<dyn Error>> {
+        let mut boosted = Vec::new();
+        for vault in self.get_active() {
+            if price > vault.get_up_price()? {
+                boosted.push(vault);
+            }
+        }
+        Ok(boosted)
+    }
+
+    fn get_active(&self) -> impl Iterator<Item = &Vault> {
+        self.vaults
+            .iter()
+            .filter(|vault| !vault.collateral.is_zero() && !vault.debt.is_zero())
+    }
+
+    pub fn show(&self, price: Option<Wad>) -> Result<(), Box<dyn Error>> {
+        let token = match self.vaults.first() {
+            Some(vault) => &vault.token,
+            None => return Ok(()),
+        };
+        let symbol = token.symbol.to_lowercase();
+        println!("{}:", token.symbol);
+        println!("{:<11}: {:>15}", "vaults", self.vaults.len());
+        println!("{:<11}: {:>15.2} {}", "collateral", self.collateral, symbol);
+        println!("{:<11}: {:>15.2} dai", "debt", self.debt);
+        println!("{:<11}:", "min ratios");
+        for (ratio, count) in &self.min_ratios {
+            println!("{:>5}% - {:>3}%: {:>5}", ratio, ratio + BUCKET, count);
+        }
+        println!("{:<11}:", "max ratios");
+        for (ratio, count) in &self.max_ratios {
+            println!("{:>5}% - {:>3}%: {:>5}", ratio, ratio + BUCKET, count);
+        }
+        if let Some(price) = price {
+            let repaid = self.get_repaid(price)?;
+            let mut dai_to_payback = Wad::zero();
+            for vault in &repaid {
+                dai_to_payback = dai_to_payback.checked_add(vault.get_down_dai_to_payback()?)?;
+            }
+            let boosted = self.get_boosted(price)?;
+            let mut dai_to_draw = Wad::zero();
+            for vault in &boosted {
+                dai_to_draw = dai_to_draw.checked_add(vault.get_up_dai_to_draw()?)?;
+            }
+            println!(
+                "at {:.2}: {} repays ({:.2} dai), {} boosts ({:.2} dai)",
+                price,
+                repaid.len(),
+                dai_to_payback,
+                boosted.len(),
+                dai_to_draw,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The lower bound of the bucket of `ratio`, whose upper bound must fit a usize too.
+fn bucket(ratio: Wad) -> Result<usize, Box<dyn Error>> {
+    let ratio_pc = ratio.to_percent();
+    if ratio_pc >= (usize::MAX - BUCKET) as f64 {
+        return Err(Box::new(MathError(format!(
+            "ratio {:.0}% overflows the buckets",
+            ratio_pc
+        ))));
+    }
+    Ok(ratio_pc as usize / BUCKET * BUCKET)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::get_default_vault;
+    use ethabi::Uint;
+
+    #[test]
+    fn boost_disabled_vaults_have_no_max_ratio_bucket() {
+        let boosted = get_default_vault().unwrap();
+        let mut unboosted = get_default_vault().unwrap();
+        unboosted.min_ratio = Wad::from_percent(165);
+        unboosted.max_ratio = Wad(Uint::from(u128::MAX));
+        let stats = SubscriberStats::new(vec![boosted, unboosted]).unwrap();
+        assert_eq!(stats.len(), 1);
+        let stats = &stats[0];
+        assert_eq!(stats.vaults.len(), 2);
+        assert_eq!(stats.debt, Wad::from_f64(20000.0).unwrap());
+        let min_ratios: Vec<_> = stats.min_ratios.clone().into_iter().collect();
+        assert_eq!(min_ratios, vec![(100, 1), (160, 1)]);
+        let max_ratios: Vec<_> = stats.max_ratios.clone().into_iter().collect();
+        assert_eq!(max_ratios, vec![(220, 1)]);
+        stats.show(Some(Wad::from_f64(100.0).unwrap())).unwrap();
+    }
+
+    #[test]
+    fn bucket_rejects_the_ratios_it_cannot_hold() {
+        assert_eq!(bucket(Wad::from_percent(175)).unwrap(), 170);
+        assert!(bucket(Wad(Uint::from(u128::MAX))).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use super::blockchain::BlockchainReader;
+use super::uniswapv2_pair::{Pair, PairError, Pool};
+use ethabi::{Address, Contract, Token};
+use std::error::Error;
+
+const FACTORY_ADDRESS: &str = "5c69bee701ef814a2b6a3edd4b1652cb9cc5aa6f";
+
+pub struct Factory<'a> {
+    blockchain_reader: &'a dyn BlockchainReader,
+    factory_address: Address,
+    factory_contract: Contract,
+}
+
+impl<'a> Factory<'a> {
+    pub fn new(blockchain_reader: &'a (dyn BlockchainReader + 'a)) -> Result<Self, Box<dyn Error>> {
+        let factory_address: Address = FACTORY_ADDRESS.parse()?;
+        let factory_abi: &[u8] = include_bytes!("abi/uniswapv2_factory.abi");
+        let factory_contract = Contract::load(factory_abi)?;
+        Ok(Self {
+            blockchain_reader,
+            factory_address,
+            factory_contract,
+        })
+    }
+
+    /// The pair of the two tokens, `None` if nobody created it.
+    pub async fn get_pair(
+        &self,
+        token_a: &Address,
+        token_b: &Address,
+    ) -> Result<Option<Address>, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(
+                &self.factory_contract,
+                &self.factory_address,
+                "getPair",
+                &[Token::Address(*token_a), Token::Address(*token_b)],
+            )
+            .await?;
+
+        let pair = tokens[0].clone().to_address().unwrap();
+        match pair.is_zero() {
+            true => Ok(None),
+            false => Ok(Some(pair)),
+        }
+    }
+
+    /// The reserves of the DAI pair of `gem`, the vault collateral.
+    pub async fn get_pool(
+        &self,
+        dai_address: &Address,
+        gem_address: &Address,
+    ) -> Result<Pool, Box<dyn Error>> {
+        match self.get_pair(dai_address, gem_address).await? {
+            Some(pair_address) => {
+                let pair = Pair::new(self.blockchain_reader, pair_address)?;
+                pair.get_pool(dai_address).await
+            }
+            None => Err(Box::new(PairError(format!(
+                "no uniswap pair for DAI and {:#x}",
+                gem_address
+            )))),
+        }
+    }
+}
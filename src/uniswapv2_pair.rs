@@ -1,6 +1,8 @@
 use super::blockchain::BlockchainReader;
+use super::math::Wad;
 use ethabi::{Address, Contract, Uint};
 use std::error::Error;
+use std::fmt;
 
 pub struct Pair<'a> {
     blockchain_reader: &'a dyn BlockchainReader,
@@ -8,6 +10,123 @@ pub struct Pair<'a> {
     pair_contract: Contract,
 }
 
+/// The result of a swap against a constant product pool, fee included.
+#[derive(Debug, Clone)]
+pub struct SwapQuote {
+    pub amount_in: Uint,
+    pub amount_out: Uint,
+    /// Relative loss between the pool mid price and the execution price (0.01 = 1%).
+    pub price_impact: f64,
+}
+
+impl SwapQuote {
+    pub fn new(
+        amount_in: Uint,
+        reserve_in: Uint,
+        reserve_out: Uint,
+    ) -> Result<Self, Box<dyn Error>> {
+        if reserve_in.is_zero() || reserve_out.is_zero() {
+            return Err(Box::new(PairError(String::from("empty reserves"))));
+        }
+        let amount_in_with_fee = amount_in * Uint::from(997);
+        let numerator = amount_in_with_fee * reserve_out;
+        let denominator = reserve_in * Uint::from(1000) + amount_in_with_fee;
+        let amount_out = numerator / denominator;
+        let mut price_impact = 0.0;
+        if !amount_in.is_zero() {
+            // the prices are ratios, any fixed point scale gives them
+            let mid_price = Wad(reserve_out).to_f64() / Wad(reserve_in).to_f64();
+            let execution_price = Wad(amount_out).to_f64() / Wad(amount_in).to_f64();
+            price_impact = 1.0 - execution_price / mid_price;
+        }
+        Ok(Self {
+            amount_in,
+            amount_out,
+            price_impact,
+        })
+    }
+
+    /// The amount to sell to receive exactly `amount_out`, as `getAmountIn` computes it.
+    pub fn for_amount_out(
+        amount_out: Uint,
+        reserve_in: Uint,
+        reserve_out: Uint,
+    ) -> Result<Self, Box<dyn Error>> {
+        if reserve_in.is_zero() || amount_out >= reserve_out {
+            return Err(Box::new(PairError(String::from(
+                "not enough liquidity in the pool",
+            ))));
+        }
+        let numerator = reserve_in * amount_out * Uint::from(1000);
+        let denominator = (reserve_out - amount_out) * Uint::from(997);
+        let amount_in = numerator / denominator + Uint::one();
+        let quote = Self::new(amount_in, reserve_in, reserve_out)?;
+        Ok(Self {
+            amount_out,
+            ..quote
+        })
+    }
+}
+
+/// The reserves of a DAI / collateral pool.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    pub dai_reserve: Uint,
+    pub col_reserve: Uint,
+}
+
+impl Pool {
+    /// Moves the pool to `price` (DAI / collateral) as arbitrageurs would, keeping its liquidity.
+    pub fn at_price(&self, price: Wad, decimals: usize) -> Result<Pool, Box<dyn Error>> {
+        // in DAI and collateral, not in their smallest units
+        let dai = Wad(self.dai_reserve).to_f64();
+        let col = Wad::from_decimals(self.col_reserve, decimals)?.to_f64();
+        let k = dai * col;
+        let price = price.to_f64();
+        if k == 0.0 || price == 0.0 {
+            return Err(Box::new(PairError(String::from("empty reserves"))));
+        }
+        Ok(Pool {
+            dai_reserve: Wad::from_f64((k * price).sqrt())?.0,
+            col_reserve: Wad::from_f64((k / price).sqrt())?.to_decimals(decimals)?,
+        })
+    }
+
+    pub fn sell_dai(&self, amount: Uint) -> Result<SwapQuote, Box<dyn Error>> {
+        SwapQuote::new(amount, self.dai_reserve, self.col_reserve)
+    }
+
+    pub fn sell_collateral(&self, amount: Uint) -> Result<SwapQuote, Box<dyn Error>> {
+        SwapQuote::new(amount, self.col_reserve, self.dai_reserve)
+    }
+
+    /// The collateral to sell to receive exactly `amount` DAI.
+    pub fn buy_dai(&self, amount: Uint) -> Result<SwapQuote, Box<dyn Error>> {
+        SwapQuote::for_amount_out(amount, self.col_reserve, self.dai_reserve)
+    }
+
+    /// The mid price, in DAI per collateral.
+    pub fn get_price(&self, decimals: usize) -> Result<Wad, Box<dyn Error>> {
+        Wad(self.dai_reserve).checked_div(Wad::from_decimals(self.col_reserve, decimals)?)
+    }
+
+    /// The reserves once `quote`, a sale of DAI, went through.
+    pub fn after_dai_sale(&self, quote: &SwapQuote) -> Pool {
+        Pool {
+            dai_reserve: self.dai_reserve + quote.amount_in,
+            col_reserve: self.col_reserve - quote.amount_out,
+        }
+    }
+
+    /// The reserves once `quote`, a sale of collateral, went through.
+    pub fn after_collateral_sale(&self, quote: &SwapQuote) -> Pool {
+        Pool {
+            dai_reserve: self.dai_reserve - quote.amount_out,
+            col_reserve: self.col_reserve + quote.amount_in,
+        }
+    }
+}
+
 impl<'a> Pair<'a> {
     pub fn new(
         blockchain_reader: &'a (dyn BlockchainReader + 'a),
@@ -22,7 +141,7 @@ impl<'a> Pair<'a> {
         })
     }
 
-    pub async fn get_price_0(&self) -> Result<f64, Box<dyn Error>> {
+    pub async fn get_reserves(&self) -> Result<(Uint, Uint), Box<dyn Error>> {
         let tokens = self
             .blockchain_reader
             .call_function(&self.pair_contract, &self.pair_address, "getReserves", &[])
@@ -32,9 +151,141 @@ impl<'a> Pair<'a> {
         let reserve0 = reserve0.unwrap();
         let reserve1 = tokens[1].clone().to_uint();
         let reserve1 = reserve1.unwrap();
+        Ok((reserve0, reserve1))
+    }
+
+    pub async fn get_token_0(&self) -> Result<Address, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function(&self.pair_contract, &self.pair_address, "token0", &[])
+            .await?;
+
+        let token_0 = tokens[0].clone().to_address();
+        Ok(token_0.unwrap())
+    }
+
+    pub async fn get_price_0(&self) -> Result<f64, Box<dyn Error>> {
+        let (reserve0, reserve1) = self.get_reserves().await?;
 
         let price0 = Uint::exp10(10).as_u128() as f64 * reserve0.as_u128() as f64
             / reserve1.as_u128() as f64;
         Ok(price0)
     }
+
+    pub async fn get_pool(&self, dai_address: &Address) -> Result<Pool, Box<dyn Error>> {
+        let (reserve0, reserve1) = self.get_reserves().await?;
+        let token_0 = self.get_token_0().await?;
+        let pool = match token_0 == *dai_address {
+            true => Pool {
+                dai_reserve: reserve0,
+                col_reserve: reserve1,
+            },
+            false => Pool {
+                dai_reserve: reserve1,
+                col_reserve: reserve0,
+            },
+        };
+        Ok(pool)
+    }
+
+    pub async fn get_quote(
+        &self,
+        token_in: &Address,
+        amount_in: Uint,
+    ) -> Result<SwapQuote, Box<dyn Error>> {
+        let (reserve0, reserve1) = self.get_reserves().await?;
+        let token_0 = self.get_token_0().await?;
+        match token_0 == *token_in {
+            true => SwapQuote::new(amount_in, reserve0, reserve1),
+            false => SwapQuote::new(amount_in, reserve1, reserve0),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PairError(pub String);
+
+impl fmt::Display for PairError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for PairError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> Pool {
+        Pool {
+            dai_reserve: Wad::from_f64(2_000_000.0).unwrap().0,
+            col_reserve: Wad::from_f64(1_000.0).unwrap().0,
+        }
+    }
+
+    #[test]
+    fn quote_takes_the_fee_on_the_amount_in() {
+        let quote = SwapQuote::new(
+            Uint::from(1_000),
+            Uint::from(1_000_000),
+            Uint::from(1_000_000),
+        )
+        .unwrap();
+        // 1000 * 997 * 1e6 / (1e6 * 1000 + 1000 * 997), rounded down
+        assert_eq!(quote.amount_out, Uint::from(996));
+    }
+
+    #[test]
+    fn quote_for_amount_out_rounds_the_amount_in_up() {
+        let reserve = Uint::from(1_000_000);
+        let quote = SwapQuote::for_amount_out(Uint::from(996), reserve, reserve).unwrap();
+        assert_eq!(quote.amount_in, Uint::from(1_000));
+        assert_eq!(quote.amount_out, Uint::from(996));
+        let sold = SwapQuote::new(quote.amount_in - Uint::one(), reserve, reserve).unwrap();
+        assert!(sold.amount_out < Uint::from(996));
+    }
+
+    #[test]
+    fn quote_for_amount_out_needs_liquidity() {
+        let reserve = Uint::from(1_000_000);
+        assert!(SwapQuote::for_amount_out(reserve, reserve, reserve).is_err());
+        assert!(SwapQuote::new(Uint::one(), Uint::zero(), reserve).is_err());
+    }
+
+    #[test]
+    fn price_impact_is_the_fee_for_small_swaps_and_grows_with_size() {
+        let pool = pool();
+        let small = pool
+            .sell_collateral(Wad::from_f64(0.001).unwrap().0)
+            .unwrap();
+        assert!((small.price_impact - 0.003).abs() < 1e-5);
+        let large = pool
+            .sell_collateral(Wad::from_f64(100.0).unwrap().0)
+            .unwrap();
+        // 1 - 0.997 / (1 + 0.997 * 0.1)
+        assert!((large.price_impact - 0.0934).abs() < 1e-3);
+    }
+
+    #[test]
+    fn at_price_moves_the_mid_price_and_keeps_the_liquidity() {
+        let pool = pool();
+        let moved = pool.at_price(Wad::from_f64(3_000.0).unwrap(), 18).unwrap();
+        let price = moved.get_price(18).unwrap().to_f64();
+        assert!((price - 3_000.0).abs() < 1e-6);
+        let k = Wad(pool.dai_reserve).to_f64() * Wad(pool.col_reserve).to_f64();
+        let moved_k = Wad(moved.dai_reserve).to_f64() * Wad(moved.col_reserve).to_f64();
+        assert!((moved_k / k - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn at_price_counts_the_collateral_decimals() {
+        let moved = pool().at_price(Wad::from_f64(3_000.0).unwrap(), 8).unwrap();
+        let price = moved.get_price(8).unwrap().to_f64();
+        assert!((price - 3_000.0).abs() < 1e-6);
+    }
 }
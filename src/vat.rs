@@ -0,0 +1,82 @@
+use super::blockchain::BlockchainReader;
+use super::math::{Rad, Ray, Wad};
+use ethabi::{Address, Contract, Token};
+use std::error::Error;
+use web3::types::BlockNumber;
+
+const VAT_ADDRESS: &str = "35d1b3f3d7966a1dfe207aa4514c12a259a0492b";
+
+pub struct Vat<'a> {
+    blockchain_reader: &'a dyn BlockchainReader,
+    vat_address: Address,
+    vat_contract: Contract,
+}
+
+impl<'a> Vat<'a> {
+    pub fn new(blockchain_reader: &'a (dyn BlockchainReader + 'a)) -> Result<Self, Box<dyn Error>> {
+        let vat_address: Address = VAT_ADDRESS.parse()?;
+        let vat_abi: &[u8] = include_bytes!("abi/vat.abi");
+        let vat_contract = Contract::load(vat_abi)?;
+        Ok(Self {
+            blockchain_reader,
+            vat_address,
+            vat_contract,
+        })
+    }
+
+    /// The collateral of the urn, `ink`, and its normalised debt, `art`.
+    pub async fn get_urn_at(
+        &self,
+        ilk_id: &[u8],
+        urn: &Address,
+        block: BlockNumber,
+    ) -> Result<(Wad, Wad), Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function_at(
+                &self.vat_contract,
+                &self.vat_address,
+                "urns",
+                &[Token::FixedBytes(ilk_id.to_vec()), Token::Address(*urn)],
+                block,
+            )
+            .await?;
+
+        let ink = tokens[0].clone().to_uint().unwrap();
+        let art = tokens[1].clone().to_uint().unwrap();
+        Ok((Wad(ink), Wad(art)))
+    }
+
+    /// The accumulated stability fee of the ilk, the debt of an urn being `art * rate`.
+    pub async fn get_rate_at(
+        &self,
+        ilk_id: &[u8],
+        block: BlockNumber,
+    ) -> Result<Ray, Box<dyn Error>> {
+        let tokens = self
+            .blockchain_reader
+            .call_function_at(
+                &self.vat_contract,
+                &self.vat_address,
+                "ilks",
+                &[Token::FixedBytes(ilk_id.to_vec())],
+                block,
+            )
+            .await?;
+
+        let rate = tokens[1].clone().to_uint().unwrap();
+        Ok(Ray(rate))
+    }
+
+    /// The collateral and the debt in DAI of the urn.
+    pub async fn get_vault_at(
+        &self,
+        ilk_id: &[u8],
+        urn: &Address,
+        block: BlockNumber,
+    ) -> Result<(Wad, Wad), Box<dyn Error>> {
+        let (ink, art) = self.get_urn_at(ilk_id, urn, block).await?;
+        let rate = self.get_rate_at(ilk_id, block).await?;
+        Ok((ink, Rad::from_product(art, rate)?.to_wad()))
+    }
+}
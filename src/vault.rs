@@ -1,166 +1,1092 @@
+use super::clipper::AuctionTerms;
 use super::erc_20::Erc20Token;
+use super::math::Wad;
+use super::uniswapv2_pair::Pool;
 use ethabi::{Address, Uint};
 use std::error::Error;
 use std::fmt;
 
+/// Past this many boosts or repays, a simulation is considered endless.
+const MAX_STEPS: usize = 1000;
+
 #[derive(Debug, Clone)]
 pub struct Vault {
     pub subscribed: bool,
-    pub min_ratio: Uint,
-    pub max_ratio: Uint,
-    pub repay_ratio: Uint,
-    pub boost_ratio: Uint,
+    pub min_ratio: Wad,
+    pub max_ratio: Wad,
+    pub repay_ratio: Wad,
+    pub boost_ratio: Wad,
     pub owner: Address,
-    pub collateral: Uint,
-    pub debt: Uint,
+    /// Counted with 18 decimals whatever the token, as the Vat does.
+    pub collateral: Wad,
+    pub debt: Wad,
     pub token: Erc20Token,
+    /// Stop-loss and take-profit rules, checked in order.
+    pub close_rules: Vec<CloseRule>,
+    /// DAI held once a close rule turned the vault into DAI.
+    pub dai: Wad,
+}
+
+/// How the automation loses value when it trades.
+#[derive(Debug, Clone)]
+pub enum Friction {
+    /// A flat percentage applied to the vault after each boost or repay.
+    Constant(f64),
+    /// Trades go through a constant product pool, so the slippage depends on their size.
+    Pool(Pool),
+    /// Each boost and repay pays its own fixed and proportional costs, as fitted by `calibrate`.
+    Calibrated { boost: TradeCost, repay: TradeCost },
+}
+
+/// The cost of one automated trade: the gas paid out of it and the share of value lost.
+#[derive(Debug, Clone, Copy)]
+pub struct TradeCost {
+    /// Gas charged on the trade, in DAI.
+    pub gas: Wad,
+    pub slippage: f64,
+}
+
+/// The net value of a position, negative once the debt exceeds the collateral value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Equity {
+    Positive(Wad),
+    /// The debt exceeds the collateral value by this amount of DAI.
+    Underwater(Wad),
+}
+
+impl Equity {
+    pub fn new(assets: Wad, liabilities: Wad) -> Result<Equity, Box<dyn Error>> {
+        match assets >= liabilities {
+            true => Ok(Equity::Positive(assets.checked_sub(liabilities)?)),
+            false => Ok(Equity::Underwater(liabilities.checked_sub(assets)?)),
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Equity::Positive(value) => value.to_f64(),
+            Equity::Underwater(deficit) => -deficit.to_f64(),
+        }
+    }
+}
+
+impl fmt::Display for Equity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.to_f64(), f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutomationAction {
+    Boost,
+    Repay,
+}
+
+impl fmt::Display for AutomationAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self {
+            AutomationAction::Boost => "boost",
+            AutomationAction::Repay => "repay",
+        };
+        f.pad(action)
+    }
+}
+
+/// One boost or repay of a simulation.
+#[derive(Debug, Clone)]
+pub struct AutomationStep {
+    pub action: AutomationAction,
+    pub trigger_price: Wad,
+    /// DAI drawn by a boost or paid back by a repay.
+    pub dai: Wad,
+    /// Collateral bought by a boost or sold by a repay, before friction.
+    pub collateral: Wad,
+    /// Net value lost to friction, in DAI.
+    pub friction: Wad,
+    /// The ratio right after the step, at the trigger price.
+    pub ratio: Option<Wad>,
+}
+
+/// Every step taken by the automation to reach a price, and the resulting vault.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub steps: Vec<AutomationStep>,
+    pub vault: Vault,
+    /// The last boost moved the up price by less than 1%, boosting again would not end.
+    pub stalled: bool,
+    /// The close rule that ended the trace, the automation stops with it.
+    pub close: Option<Close>,
+}
+
+/// What a close rule turns the vault into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CloseTo {
+    /// Every collateral is sold, the debt repaid and the rest kept in DAI.
+    Dai,
+    /// Only the collateral needed to repay the debt is sold.
+    Collateral,
+}
+
+impl fmt::Display for CloseTo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let to = match self {
+            CloseTo::Dai => "dai",
+            CloseTo::Collateral => "collateral",
+        };
+        f.pad(to)
+    }
+}
+
+/// The price move that triggers a close rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CloseTrigger {
+    /// Once the price falls to this price.
+    StopLoss(Wad),
+    /// Once the price rises to this price.
+    TakeProfit(Wad),
+    /// Once the price falls `distance` (0.1 for 10%) below `high`, the highest price seen.
+    TrailingStopLoss { distance: Wad, high: Wad },
+    /// Once `high` went past `activation`, when the price falls `distance` below it.
+    TrailingTakeProfit {
+        activation: Wad,
+        distance: Wad,
+        high: Wad,
+    },
+}
+
+/// Closes the vault to DAI or to collateral when the price reaches a trigger.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseRule {
+    pub trigger: CloseTrigger,
+    pub to: CloseTo,
+}
+
+impl CloseRule {
+    /// Follows the highest price seen, for the trailing rules.
+    pub fn observe(&mut self, price: Wad) {
+        match &mut self.trigger {
+            CloseTrigger::TrailingStopLoss { high, .. }
+            | CloseTrigger::TrailingTakeProfit { high, .. }
+                if price > *high =>
+            {
+                *high = price
+            }
+            _ => {}
+        }
+    }
+
+    /// The price the rule closes at once the price reached `price`, `None` when it does not
+    /// trigger.
+    pub fn get_trigger_price(&self, price: Wad) -> Result<Option<Wad>, Box<dyn Error>> {
+        let trigger_price = match &self.trigger {
+            CloseTrigger::StopLoss(stop) if price <= *stop => Some(*stop),
+            CloseTrigger::TakeProfit(target) if price >= *target => Some(*target),
+            CloseTrigger::TrailingStopLoss { distance, high } => {
+                let stop = high.checked_mul(Wad::one().saturating_sub(*distance))?;
+                match price <= stop {
+                    true => Some(stop),
+                    false => None,
+                }
+            }
+            CloseTrigger::TrailingTakeProfit {
+                activation,
+                distance,
+                high,
+            } if high >= activation => {
+                let stop = high.checked_mul(Wad::one().saturating_sub(*distance))?;
+                match price <= stop {
+                    true => Some(stop),
+                    false => None,
+                }
+            }
+            _ => None,
+        };
+        Ok(trigger_price)
+    }
+}
+
+impl fmt::Display for CloseRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let trigger = match &self.trigger {
+            CloseTrigger::StopLoss(stop) => format!("stop-loss at {:.2}", stop),
+            CloseTrigger::TakeProfit(target) => format!("take-profit at {:.2}", target),
+            CloseTrigger::TrailingStopLoss { distance, high } => format!(
+                "trailing stop-loss {:.2}% below {:.2}",
+                distance.to_percent(),
+                high
+            ),
+            CloseTrigger::TrailingTakeProfit {
+                activation,
+                distance,
+                high,
+            } => format!(
+                "trailing take-profit {:.2}% below {:.2} past {:.2}",
+                distance.to_percent(),
+                high,
+                activation
+            ),
+        };
+        f.pad(&format!("{} to {}", trigger, self.to))
+    }
+}
+
+/// A close rule that triggered.
+#[derive(Debug, Clone)]
+pub struct Close {
+    pub rule: CloseRule,
+    pub trigger_price: Wad,
+    /// The price the collateral was sold at.
+    pub price: Wad,
+    pub collateral_sold: Wad,
+    /// Net value lost to friction, in DAI.
+    pub friction: Wad,
+    /// The debt the whole collateral could not repay, the vault is then a total loss.
+    pub shortfall: Wad,
+}
+
+/// When and how the keepers liquidate a vault.
+#[derive(Debug, Clone)]
+pub struct LiquidationTerms {
+    /// The ilk liquidation ratio, `mat`.
+    pub ratio: Wad,
+    /// The debt is repaid times this factor, e.g. 1.13 for a 13% penalty, the Dog `chop`.
+    pub penalty: Wad,
+    /// Without auction, the collateral is sold at the market price.
+    pub auction: Option<AuctionTerms>,
+}
+
+/// The keepers selling the collateral to cover the debt and the penalty.
+#[derive(Debug, Clone)]
+pub struct Liquidation {
+    /// The oracle price that made the vault liquidatable.
+    pub trigger_price: Wad,
+    /// The market price when the collateral was sold.
+    pub price: Wad,
+    /// The price the auction sold the collateral at.
+    pub sale_price: Wad,
+    pub debt: Wad,
+    pub collateral_sold: Wad,
+    /// The collateral left once the debt and the penalty are covered, given back to the owner.
+    pub collateral_returned: Wad,
+    /// The equity lost on top of the debt repaid, to the penalty and to the auction
+    /// discount, in DAI.
+    pub penalty_loss: Wad,
 }
 
+/// What the Vat lets a manual change of a vault do.
+#[derive(Debug, Clone)]
+pub struct VatLimits {
+    /// Drawing or withdrawing cannot take the ratio below it at the oracle price, `mat`.
+    pub liquidation_ratio: Wad,
+    /// The smallest debt a vault can have, unless it has none.
+    pub dust: Wad,
+    /// The DAI the ilk can still mint before its debt ceiling.
+    pub available_debt: Wad,
+}
+
+/// What the vault would look like after the automation ran down (or up) to `price`.
+#[derive(Debug, Clone)]
+pub struct PricePoint {
+    pub price: Wad,
+    pub col: Wad,
+    pub debt: Wad,
+    pub equity: Equity,
+    /// `None` when there is no debt.
+    pub ratio: Option<Wad>,
+    /// The boosts stopped moving the up price, the projection is where they stalled.
+    pub stalled: bool,
+}
+
+/// The vault analysed over a price range.
 #[derive(Debug, Clone)]
 pub struct VaultInfo {
-    pub col: Uint,
-    pub debt: Uint,
+    /// The current collateral and debt, at `price`.
+    pub col: Wad,
+    pub debt: Wad,
+    pub price: Wad,
+    pub equity: Equity,
+    pub ratio: Option<Wad>,
+    /// The boost trigger, when it falls inside the range.
+    pub up_price: Option<Wad>,
+    /// The repay trigger, when it falls inside the range.
+    pub down_price: Option<Wad>,
+    /// The projections at evenly spaced prices, from low to high.
+    pub points: Vec<PricePoint>,
+    /// The sampled prices that could not be projected, with the reason.
+    pub failed: Vec<(Wad, String)>,
 }
 
 impl Vault {
-    pub fn get_dai_value(&self, price: Uint) -> Result<Uint, Box<dyn Error>> {
-        let dai_col = price * self.collateral / Uint::exp10(self.token.decimals);
-        Ok(dai_col - self.debt)
+    pub fn get_equity(&self, price: Wad) -> Result<Equity, Box<dyn Error>> {
+        let assets = self.collateral.checked_mul(price)?.checked_add(self.dai)?;
+        Equity::new(assets, self.debt)
     }
-    pub fn get_col_value(&self, price: Uint) -> Result<Uint, Box<dyn Error>> {
+
+    /// The net value in DAI, an error when the vault is underwater.
+    pub fn get_dai_value(&self, price: Wad) -> Result<Wad, Box<dyn Error>> {
+        match self.get_equity(price)? {
+            Equity::Positive(value) => Ok(value),
+            Equity::Underwater(deficit) => Err(Box::new(VaultError(format!(
+                "the vault is underwater by {:.2} dai at {:.2}",
+                deficit, price
+            )))),
+        }
+    }
+
+    pub fn get_col_value(&self, price: Wad) -> Result<Wad, Box<dyn Error>> {
         let dai_value = self.get_dai_value(price)?;
-        Ok(dai_value * Uint::exp10(self.token.decimals) / price)
+        dai_value.checked_div(price)
     }
 
+    /// Analyses the vault between `low` and `high`, projecting it with `trace_vault` at
+    /// `samples` evenly spaced prices (both bounds included). A sample that fails is recorded
+    /// in `failed` and does not stop the others.
     pub fn get_vault_info(
         &self,
-        _price: Uint,
-        _low: Uint,
-        _high: Uint,
-        _friction: f64,
+        price: Wad,
+        low: Wad,
+        high: Wad,
+        friction: &Friction,
+        samples: usize,
     ) -> Result<VaultInfo, Box<dyn Error>> {
+        if low >= high {
+            return Err(Box::new(VaultError(format!(
+                "the low price ({:.2}) must be below the high price ({:.2})",
+                low, high
+            ))));
+        }
+        if samples < 2 {
+            return Err(Box::new(VaultError(String::from(
+                "at least 2 samples are needed, one for each bound",
+            ))));
+        }
+        let in_range = |trigger: Wad| match trigger >= low && trigger <= high {
+            true => Some(trigger),
+            false => None,
+        };
+        let step = Wad(high.checked_sub(low)?.0 / Uint::from(samples - 1));
+        let mut points = Vec::with_capacity(samples);
+        let mut failed = Vec::new();
+        for i in 0..samples {
+            let sample_price = match i == samples - 1 {
+                true => high,
+                false => low.checked_add(Wad(step.0 * Uint::from(i)))?,
+            };
+            let trace = match self.trace_vault(sample_price, friction) {
+                Ok(trace) => trace,
+                Err(e) => {
+                    failed.push((sample_price, e.to_string()));
+                    continue;
+                }
+            };
+            points.push(PricePoint {
+                price: sample_price,
+                col: trace.vault.collateral,
+                debt: trace.vault.debt,
+                equity: trace.vault.get_equity(sample_price)?,
+                ratio: trace.vault.get_ratio(sample_price)?,
+                stalled: trace.stalled,
+            });
+        }
         Ok(VaultInfo {
             col: self.collateral,
             debt: self.debt,
+            price,
+            equity: self.get_equity(price)?,
+            ratio: self.get_ratio(price)?,
+            up_price: in_range(self.get_up_price()?),
+            down_price: in_range(self.get_down_price()?),
+            points,
+            failed,
         })
     }
 
-    pub fn get_up_price(&self) -> Result<Uint, Box<dyn Error>> {
-        let up_price = self.debt * self.max_ratio / self.collateral;
-        Ok(up_price)
+    /// The Saver stores the max ratio of a vault with boost disabled as the largest uint128.
+    pub fn is_boost_enabled(&self) -> bool {
+        self.max_ratio.0 < Uint::from(u128::MAX)
     }
 
-    pub fn get_down_price(&self) -> Result<Uint, Box<dyn Error>> {
-        let down_price = self.debt * self.min_ratio / self.collateral;
-        Ok(down_price)
+    /// Checks the ratios against the Saver rules, `min_limit` being the lowest min ratio
+    /// accepted for the ilk and `liquidation_ratio` its `mat`. An unsubscribed vault has no
+    /// ratio to check, and without boost the max and boost ratios are not used.
+    pub fn check_ratios(
+        &self,
+        min_limit: Wad,
+        liquidation_ratio: Wad,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.subscribed {
+            return Ok(());
+        }
+        let mut errors = Vec::new();
+        if self.min_ratio <= liquidation_ratio {
+            errors.push(format!(
+                "min ratio ({}%) must be above the liquidation ratio ({}%)",
+                self.min_ratio.to_percent(),
+                liquidation_ratio.to_percent()
+            ));
+        }
+        if self.min_ratio < min_limit {
+            errors.push(format!(
+                "min ratio ({}%) must be at least the Saver limit ({}%)",
+                self.min_ratio.to_percent(),
+                min_limit.to_percent()
+            ));
+        }
+        if self.repay_ratio <= self.min_ratio {
+            errors.push(format!(
+                "repay ratio ({}%) must be above min ratio ({}%)",
+                self.repay_ratio.to_percent(),
+                self.min_ratio.to_percent()
+            ));
+        }
+        if !self.is_boost_enabled() {
+            return match errors.is_empty() {
+                true => Ok(()),
+                false => Err(Box::new(VaultError(errors.join("\n")))),
+            };
+        }
+        if self.min_ratio >= self.max_ratio {
+            errors.push(format!(
+                "min ratio ({}%) must be below max ratio ({}%)",
+                self.min_ratio.to_percent(),
+                self.max_ratio.to_percent()
+            ));
+        }
+        if self.repay_ratio >= self.max_ratio {
+            errors.push(format!(
+                "repay ratio ({}%) must be below max ratio ({}%)",
+                self.repay_ratio.to_percent(),
+                self.max_ratio.to_percent()
+            ));
+        }
+        if self.boost_ratio <= self.min_ratio || self.boost_ratio >= self.max_ratio {
+            errors.push(format!(
+                "boost ratio ({}%) must be between min ratio ({}%) and max ratio ({}%)",
+                self.boost_ratio.to_percent(),
+                self.min_ratio.to_percent(),
+                self.max_ratio.to_percent()
+            ));
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(Box::new(VaultError(errors.join("\n"))))
     }
 
-    pub fn get_liquidation_price(&self) -> Result<Uint, Box<dyn Error>> {
-        let liquidation_price = self.debt * Uint::from(150) * Uint::exp10(16) / self.collateral;
-        Ok(liquidation_price)
+    pub fn get_up_price(&self) -> Result<Wad, Box<dyn Error>> {
+        self.get_price_at_ratio(self.max_ratio)
     }
 
-    pub fn get_up_dai_to_draw(&self) -> Result<Uint, Box<dyn Error>> {
-        let up_price = self.get_up_price()?;
-        let dai_value = self.get_dai_value(up_price)?;
-        let final_debt = dai_value * Uint::exp10(18) / (self.boost_ratio - Uint::exp10(18));
-        let mut dai_to_draw = Uint::zero();
+    pub fn get_down_price(&self) -> Result<Wad, Box<dyn Error>> {
+        self.get_price_at_ratio(self.min_ratio)
+    }
+
+    /// The price at which the vault can be bitten, `liquidation_ratio` being the ilk `mat`.
+    pub fn get_liquidation_price(&self, liquidation_ratio: Wad) -> Result<Wad, Box<dyn Error>> {
+        self.get_price_at_ratio(liquidation_ratio)
+    }
+
+    /// The price at which the collateral is worth `ratio` times the debt.
+    pub fn get_price_at_ratio(&self, ratio: Wad) -> Result<Wad, Box<dyn Error>> {
+        if self.collateral.is_zero() {
+            return Err(Box::new(VaultError(String::from(
+                "the vault has no collateral",
+            ))));
+        }
+        self.debt.checked_mul(ratio)?.checked_div(self.collateral)
+    }
+
+    /// The collateralization ratio at `price`, `None` when there is no debt.
+    pub fn get_ratio(&self, price: Wad) -> Result<Option<Wad>, Box<dyn Error>> {
+        if self.debt.is_zero() {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.collateral.checked_mul(price)?.checked_div(self.debt)?,
+        ))
+    }
+
+    pub fn is_liquidatable(
+        &self,
+        price: Wad,
+        liquidation_ratio: Wad,
+    ) -> Result<bool, Box<dyn Error>> {
+        match self.get_ratio(price)? {
+            Some(ratio) => Ok(ratio < liquidation_ratio),
+            None => Ok(false),
+        }
+    }
+
+    pub fn get_up_dai_to_draw(&self) -> Result<Wad, Box<dyn Error>> {
+        self.get_dai_to_draw_at(self.get_up_price()?)
+    }
+
+    /// The DAI a boost triggered at `price` draws to bring the ratio down to the boost ratio.
+    fn get_dai_to_draw_at(&self, price: Wad) -> Result<Wad, Box<dyn Error>> {
+        if self.boost_ratio <= Wad::one() {
+            return Err(Box::new(VaultError(format!(
+                "boost ratio ({}%) must be above 100%",
+                self.boost_ratio.to_percent()
+            ))));
+        }
+        let dai_value = self.get_dai_value(price)?;
+        let final_debt = dai_value.checked_div(self.boost_ratio.checked_sub(Wad::one())?)?;
+        Ok(final_debt.saturating_sub(self.debt))
+    }
+
+    pub fn get_down_dai_to_payback(&self) -> Result<Wad, Box<dyn Error>> {
+        self.get_dai_to_payback_at(self.get_down_price()?)
+    }
+
+    /// The DAI a repay triggered at `price` pays back to bring the ratio up to the repay ratio.
+    fn get_dai_to_payback_at(&self, price: Wad) -> Result<Wad, Box<dyn Error>> {
+        if self.repay_ratio <= Wad::one() {
+            return Err(Box::new(VaultError(format!(
+                "repay ratio ({}%) must be above 100%",
+                self.repay_ratio.to_percent()
+            ))));
+        }
+        let dai_value = self.get_dai_value(price)?;
+        let final_debt = dai_value.checked_div(self.repay_ratio.checked_sub(Wad::one())?)?;
         if final_debt > self.debt {
-            dai_to_draw = final_debt - self.debt;
+            return Err(Box::new(VaultError(format!(
+                "repay ratio ({}%) must be above min ratio ({}%), a repay would draw more dai",
+                self.repay_ratio.to_percent(),
+                self.min_ratio.to_percent()
+            ))));
         }
-        Ok(dai_to_draw)
+        self.debt.checked_sub(final_debt)
     }
 
-    pub fn get_down_dai_to_payback(&self) -> Result<Uint, Box<dyn Error>> {
-        let down_price = self.get_down_price()?;
-        let dai_value = self.get_dai_value(down_price)?;
-        let final_debt = dai_value * Uint::exp10(18) / (self.repay_ratio - Uint::exp10(18));
-        let dai_to_payback = self.debt - final_debt;
-        Ok(dai_to_payback)
+    pub fn get_up_vault(&self, friction: &Friction) -> Result<Vault, Box<dyn Error>> {
+        let up_price = self.get_up_price()?;
+        self.boost_at(up_price, up_price, friction)
     }
 
-    pub fn get_up_vault(&self, friction: f64) -> Result<Vault, Box<dyn Error>> {
+    /// A boost sized at the oracle `price`, the collateral being bought at `market_price`.
+    fn boost_at(
+        &self,
+        price: Wad,
+        market_price: Wad,
+        friction: &Friction,
+    ) -> Result<Vault, Box<dyn Error>> {
         let mut up_vault = self.clone();
-        let dai_to_draw = self.get_up_dai_to_draw()?;
-        if dai_to_draw != Uint::zero() {
-            let up_price = self.get_up_price()?;
-            up_vault.collateral = up_vault.collateral + (dai_to_draw * Uint::exp10(18) / up_price);
-            up_vault.collateral =
-                Uint::from((up_vault.collateral.as_u128() as f64 * (1.0 - friction)) as u128);
-            up_vault.debt = up_vault.debt + dai_to_draw;
-            up_vault.debt = Uint::from((up_vault.debt.as_u128() as f64 * (1.0 - friction)) as u128);
+        let dai_to_draw = self.get_dai_to_draw_at(price)?;
+        if !dai_to_draw.is_zero() {
+            match friction {
+                Friction::Constant(friction) => {
+                    let kept = Wad::from_f64(1.0 - friction)?;
+                    up_vault.collateral = up_vault
+                        .collateral
+                        .checked_add(dai_to_draw.checked_div(market_price)?)?
+                        .checked_mul(kept)?;
+                    up_vault.debt = up_vault.debt.checked_add(dai_to_draw)?.checked_mul(kept)?;
+                }
+                Friction::Pool(pool) => {
+                    let pool = pool.at_price(market_price, self.token.decimals)?;
+                    let bought = pool.sell_dai(dai_to_draw.0)?.amount_out;
+                    up_vault.collateral = up_vault
+                        .collateral
+                        .checked_add(Wad::from_decimals(bought, self.token.decimals)?)?;
+                    up_vault.debt = up_vault.debt.checked_add(dai_to_draw)?;
+                }
+                Friction::Calibrated { boost, .. } => {
+                    let dai_to_sell = dai_to_draw.saturating_sub(boost.gas);
+                    let bought = dai_to_sell
+                        .checked_div(market_price)?
+                        .checked_mul(Wad::from_f64(1.0 - boost.slippage)?)?;
+                    up_vault.collateral = up_vault.collateral.checked_add(bought)?;
+                    up_vault.debt = up_vault.debt.checked_add(dai_to_draw)?;
+                }
+            }
         }
         Ok(up_vault)
     }
 
-    pub fn get_down_vault(&self) -> Result<Vault, Box<dyn Error>> {
+    pub fn get_down_vault(&self, friction: &Friction) -> Result<Vault, Box<dyn Error>> {
+        let down_price = self.get_down_price()?;
+        self.repay_at(down_price, down_price, friction)
+    }
+
+    /// A repay sized at the oracle `price`, the collateral being sold at `market_price`.
+    fn repay_at(
+        &self,
+        price: Wad,
+        market_price: Wad,
+        friction: &Friction,
+    ) -> Result<Vault, Box<dyn Error>> {
         let mut down_vault = self.clone();
-        let dai_to_payback = down_vault.get_down_dai_to_payback()?;
-        let down_price = down_vault.get_down_price()?;
-        down_vault.collateral =
-            down_vault.collateral - (dai_to_payback * Uint::exp10(18) / down_price);
-        down_vault.debt = down_vault.debt - dai_to_payback;
+        let dai_to_payback = down_vault.get_dai_to_payback_at(price)?;
+        let col_to_sell = dai_to_payback.checked_div(price)?;
+        down_vault.collateral = down_vault.collateral.checked_sub(col_to_sell)?;
+        let sold_value = match price == market_price {
+            true => dai_to_payback,
+            false => col_to_sell.checked_mul(market_price)?,
+        };
+        let paid_back = match friction {
+            Friction::Constant(_) => sold_value,
+            Friction::Pool(pool) => {
+                let pool = pool.at_price(market_price, self.token.decimals)?;
+                let col_to_sell = col_to_sell.to_decimals(self.token.decimals)?;
+                Wad(pool.sell_collateral(col_to_sell)?.amount_out)
+            }
+            Friction::Calibrated { repay, .. } => sold_value
+                .checked_mul(Wad::from_f64(1.0 - repay.slippage)?)?
+                .saturating_sub(repay.gas),
+        };
+        down_vault.debt = down_vault.debt.checked_sub(paid_back)?;
         Ok(down_vault)
     }
 
-    pub fn predict_vault(&self, price: Uint, friction: f64) -> Result<Vault, Box<dyn Error>> {
-        let up_price = self.get_up_price()?;
-        let down_price = self.get_down_price()?;
+    pub fn predict_vault(&self, price: Wad, friction: &Friction) -> Result<Vault, Box<dyn Error>> {
+        let trace = self.trace_vault(price, friction)?;
+        if trace.stalled {
+            return Err(Box::new(VaultError(String::from("increase too low"))));
+        }
+        Ok(trace.vault)
+    }
+
+    /// `predict_vault` behind a delayed oracle, see `trace_vault_delayed`.
+    pub fn predict_vault_delayed(
+        &self,
+        oracle_price: Wad,
+        market_price: Wad,
+        friction: &Friction,
+    ) -> Result<Vault, Box<dyn Error>> {
+        Ok(self
+            .trace_vault_delayed(oracle_price, market_price, friction)?
+            .vault)
+    }
+
+    /// Boosts or repays until `price` sits between the down and up prices, one step at a time.
+    pub fn trace_vault(&self, price: Wad, friction: &Friction) -> Result<Trace, Box<dyn Error>> {
+        self.trace_steps(price, None, friction)
+    }
+
+    /// Boosts or repays as the automation does behind the OSM: the steps trigger on, and are
+    /// sized at, the lagged `oracle_price` while the trades execute at `market_price`.
+    pub fn trace_vault_delayed(
+        &self,
+        oracle_price: Wad,
+        market_price: Wad,
+        friction: &Friction,
+    ) -> Result<Trace, Box<dyn Error>> {
+        self.trace_steps(oracle_price, Some(market_price), friction)
+    }
+
+    /// Without a `market_price`, the automation is assumed to watch the market and each step
+    /// executes right at its trigger.
+    fn trace_steps(
+        &self,
+        price: Wad,
+        market_price: Option<Wad>,
+        friction: &Friction,
+    ) -> Result<Trace, Box<dyn Error>> {
         let mut vault = self.clone();
-        if price > up_price {
-            vault = self.get_up_vault(friction)?;
-            let new_up_price = vault.get_up_price()?;
-            let increase = new_up_price.as_u128() as f64 / up_price.as_u128() as f64;
-            if increase < 1.01 {
-                return Err(Box::new(VaultError(String::from("increase too low"))));
+        for rule in vault.close_rules.iter_mut() {
+            rule.observe(price);
+        }
+        let mut steps = Vec::new();
+        loop {
+            if steps.len() == MAX_STEPS {
+                return Err(Box::new(VaultError(format!(
+                    "still no stable vault after {} steps",
+                    MAX_STEPS
+                ))));
+            }
+            let up_price = vault.get_up_price()?;
+            let down_price = vault.get_down_price()?;
+            if let Some((rule, trigger_price)) = vault.get_close_trigger(price)? {
+                // without oracle delay, a rule closer than the next step closes first
+                let first = match market_price {
+                    Some(_) => true,
+                    None if price > up_price => trigger_price <= up_price,
+                    None if price < down_price => trigger_price >= down_price,
+                    None => true,
+                };
+                if first {
+                    let execution_price = market_price.unwrap_or(trigger_price);
+                    let (next, close) =
+                        vault.close(&rule, trigger_price, execution_price, friction)?;
+                    return Ok(Trace {
+                        steps,
+                        vault: next,
+                        stalled: false,
+                        close: Some(close),
+                    });
+                }
+            }
+            // underwater at the market price, every trade sized at the oracle price would lose
+            // more, the vault is left to the keepers
+            if let Some(market_price) = market_price {
+                if let Equity::Underwater(_) = vault.get_equity(market_price)? {
+                    break;
+                }
+            }
+            let trigger = |threshold: Wad| match market_price {
+                Some(market_price) => (price, market_price),
+                None => (threshold, threshold),
+            };
+            let (action, trigger_price, execution_price, dai, next) = if price > up_price {
+                let (trigger_price, execution_price) = trigger(up_price);
+                (
+                    AutomationAction::Boost,
+                    trigger_price,
+                    execution_price,
+                    vault.get_dai_to_draw_at(trigger_price)?,
+                    vault.boost_at(trigger_price, execution_price, friction)?,
+                )
+            } else if price < down_price {
+                let (trigger_price, execution_price) = trigger(down_price);
+                let dai = vault.get_dai_to_payback_at(trigger_price)?;
+                let mut next = vault.repay_at(trigger_price, execution_price, friction)?;
+                if let Friction::Constant(friction) = friction {
+                    let kept = Wad::from_f64(1.0 - friction)?;
+                    next.debt = next.debt.checked_mul(kept)?;
+                    next.collateral = next.collateral.checked_mul(kept)?;
+                }
+                (
+                    AutomationAction::Repay,
+                    trigger_price,
+                    execution_price,
+                    dai,
+                    next,
+                )
+            } else {
+                break;
+            };
+            // a trade at the market price keeps the net value, what is missing is friction
+            let lost = Equity::new(
+                vault
+                    .collateral
+                    .checked_mul(execution_price)?
+                    .checked_add(next.debt)?,
+                next.collateral
+                    .checked_mul(execution_price)?
+                    .checked_add(vault.debt)?,
+            )?;
+            steps.push(AutomationStep {
+                action: action.clone(),
+                trigger_price,
+                dai,
+                collateral: dai.checked_div(trigger_price)?,
+                friction: match lost {
+                    Equity::Positive(lost) => lost,
+                    Equity::Underwater(_) => Wad::zero(),
+                },
+                ratio: next.get_ratio(trigger_price)?,
+            });
+            // behind the oracle, the boost is sized at the oracle price and cannot loop
+            let stalled = market_price.is_none()
+                && action == AutomationAction::Boost
+                && next.get_up_price()?.checked_div(up_price)? < Wad::from_percent(101);
+            vault = next;
+            if stalled {
+                return Ok(Trace {
+                    steps,
+                    vault,
+                    stalled,
+                    close: None,
+                });
             }
-            vault = vault.predict_vault(price, friction)?;
         }
-        if price < down_price {
-            vault = vault.get_down_vault()?.predict_vault(price, friction)?;
-            vault.debt = Uint::from((vault.debt.as_u128() as f64 * (1.0 - friction)) as u128);
-            vault.collateral =
-                Uint::from((vault.collateral.as_u128() as f64 * (1.0 - friction)) as u128);
+        Ok(Trace {
+            steps,
+            vault,
+            stalled: false,
+            close: None,
+        })
+    }
+
+    /// The first close rule triggered by `price`, with the price it triggers at.
+    pub fn get_close_trigger(
+        &self,
+        price: Wad,
+    ) -> Result<Option<(CloseRule, Wad)>, Box<dyn Error>> {
+        for rule in &self.close_rules {
+            if let Some(trigger_price) = rule.get_trigger_price(price)? {
+                return Ok(Some((rule.clone(), trigger_price)));
+            }
         }
+        Ok(None)
+    }
+
+    /// Applies `rule`, triggered at `trigger_price`, the collateral being sold at `price`.
+    /// The closed vault has no debt and no rule left, and is unsubscribed. When the collateral
+    /// does not cover the debt, everything is lost and the unpaid debt is the `shortfall`.
+    pub fn close(
+        &self,
+        rule: &CloseRule,
+        trigger_price: Wad,
+        price: Wad,
+        friction: &Friction,
+    ) -> Result<(Vault, Close), Box<dyn Error>> {
+        let mut vault = self.clone();
+        let received_for_all = self.sell_collateral(self.collateral, price, friction)?;
+        // underwater, every collateral goes to the debt and the rest of it is left unpaid
+        let shortfall = self.debt.saturating_sub(received_for_all);
+        let (collateral_sold, received) = match (!shortfall.is_zero(), rule.to) {
+            (true, _) | (false, CloseTo::Dai) => (self.collateral, received_for_all),
+            (false, CloseTo::Collateral) => {
+                let collateral_sold = self
+                    .get_collateral_to_sell(self.debt, price, friction)?
+                    .min(self.collateral);
+                let received = self.sell_collateral(collateral_sold, price, friction)?;
+                (collateral_sold, received)
+            }
+        };
+        vault.collateral = vault.collateral.checked_sub(collateral_sold)?;
+        vault.dai = vault.dai.checked_add(received.saturating_sub(self.debt))?;
+        vault.debt = Wad::zero();
+        vault.close_rules.clear();
+        vault.subscribed = false;
+        let close = Close {
+            rule: rule.clone(),
+            trigger_price,
+            price,
+            collateral_sold,
+            friction: collateral_sold.checked_mul(price)?.saturating_sub(received),
+            shortfall,
+        };
+        Ok((vault, close))
+    }
+
+    /// The DAI received for `amount` of collateral sold at `price`, friction included.
+    fn sell_collateral(
+        &self,
+        amount: Wad,
+        price: Wad,
+        friction: &Friction,
+    ) -> Result<Wad, Box<dyn Error>> {
+        let value = amount.checked_mul(price)?;
+        match friction {
+            Friction::Constant(friction) => value.checked_mul(Wad::from_f64(1.0 - friction)?),
+            Friction::Pool(pool) => {
+                let pool = pool.at_price(price, self.token.decimals)?;
+                let amount = amount.to_decimals(self.token.decimals)?;
+                Ok(Wad(pool.sell_collateral(amount)?.amount_out))
+            }
+            Friction::Calibrated { repay, .. } => Ok(value
+                .checked_mul(Wad::from_f64(1.0 - repay.slippage)?)?
+                .saturating_sub(repay.gas)),
+        }
+    }
+
+    /// The collateral to sell at `price` to receive `dai`, friction included.
+    fn get_collateral_to_sell(
+        &self,
+        dai: Wad,
+        price: Wad,
+        friction: &Friction,
+    ) -> Result<Wad, Box<dyn Error>> {
+        match friction {
+            Friction::Constant(friction) => dai
+                .checked_div(price)?
+                .checked_div(Wad::from_f64(1.0 - friction)?),
+            Friction::Pool(pool) => {
+                let pool = pool.at_price(price, self.token.decimals)?;
+                let amount_in = pool.buy_dai(dai.0)?.amount_in;
+                Wad::from_decimals(amount_in, self.token.decimals)
+            }
+            Friction::Calibrated { repay, .. } => dai
+                .checked_add(repay.gas)?
+                .checked_div(price)?
+                .checked_div(Wad::from_f64(1.0 - repay.slippage)?),
+        }
+    }
+
+    /// The keepers buy the collateral in auction, when its price goes below the market
+    /// `price`, until the debt and the penalty are repaid. What is left goes back to the
+    /// owner. `trigger_price` is the oracle price the auction started at.
+    pub fn liquidate(
+        &self,
+        trigger_price: Wad,
+        price: Wad,
+        terms: &LiquidationTerms,
+    ) -> Result<(Vault, Liquidation), Box<dyn Error>> {
+        let due = self.debt.checked_mul(terms.penalty)?;
+        let sale_price = match &terms.auction {
+            Some(auction) => auction.get_clearing_price(trigger_price, price)?,
+            None => price,
+        };
+        let collateral_sold = std::cmp::min(self.collateral, due.checked_div(sale_price)?);
+        let mut vault = self.clone();
+        vault.collateral = vault.collateral.checked_sub(collateral_sold)?;
+        vault.debt = Wad::zero();
+        let liquidation = Liquidation {
+            trigger_price,
+            price,
+            sale_price,
+            debt: self.debt,
+            collateral_sold,
+            collateral_returned: vault.collateral,
+            // past the debt, the collateral sold pays the penalty and the auction discount
+            penalty_loss: collateral_sold
+                .checked_mul(price)?
+                .saturating_sub(self.debt),
+        };
+        Ok((vault, liquidation))
+    }
+
+    pub fn deposit(&self, amount: Wad) -> Result<Vault, Box<dyn Error>> {
+        let mut vault = self.clone();
+        vault.collateral = vault.collateral.checked_add(amount)?;
         Ok(vault)
     }
 
-    pub fn show(&self, price: Uint, btc_price: f64) -> Result<(), Box<dyn Error>> {
-        let price_f64 = price.as_u128() as f64 / Uint::exp10(18).as_u128() as f64;
-        let dai_value =
-            self.get_dai_value(price)?.as_u128() as f64 / Uint::exp10(18).as_u128() as f64;
-        let eur_value = dai_value / 1.2271;
-        let col_value =
-            self.get_col_value(price)?.as_u128() as f64 / Uint::exp10(18).as_u128() as f64;
-        let btc_value = col_value * btc_price;
-        let down_price = self.get_down_price()?.as_u128() as f64 / Uint::exp10(18).as_u128() as f64;
-        let up_price = self.get_up_price()?.as_u128() as f64 / Uint::exp10(18).as_u128() as f64;
-        let liquidation_price =
-            self.get_liquidation_price()?.as_u128() as f64 / Uint::exp10(18).as_u128() as f64;
-        let max_ratio_pc = self.max_ratio.as_u128() as f64 / Uint::exp10(16).as_u128() as f64;
-        let min_ratio_pc = self.min_ratio.as_u128() as f64 / Uint::exp10(16).as_u128() as f64;
-        let boost_ratio_pc = self.boost_ratio.as_u128() as f64 / Uint::exp10(16).as_u128() as f64;
-        let repay_ratio_pc = self.repay_ratio.as_u128() as f64 / Uint::exp10(16).as_u128() as f64;
-
-        let col_dai =
-            self.collateral.as_u128() as f64 * price_f64 / Uint::exp10(18).as_u128() as f64;
-        let debt = self.debt.as_u128() as f64 / Uint::exp10(18).as_u128() as f64;
-        let ratio_pc = col_dai * 100.0 / debt;
-        println!("{:<11}: {:>9.2} ({:.2}%)", "price", price_f64, ratio_pc);
+    /// Fails as the Vat would, when `price` is the oracle price.
+    pub fn withdraw(
+        &self,
+        amount: Wad,
+        price: Wad,
+        limits: &VatLimits,
+    ) -> Result<Vault, Box<dyn Error>> {
+        if amount > self.collateral {
+            return Err(Box::new(VaultError(format!(
+                "cannot withdraw {:.4}, the vault only has {:.4} {}",
+                amount,
+                self.collateral,
+                self.token.symbol.to_lowercase()
+            ))));
+        }
+        let mut vault = self.clone();
+        vault.collateral = vault.collateral.checked_sub(amount)?;
+        vault.check_vat(true, price, limits)?;
+        Ok(vault)
+    }
+
+    /// Fails as the Vat would, when `price` is the oracle price.
+    pub fn draw(
+        &self,
+        amount: Wad,
+        price: Wad,
+        limits: &VatLimits,
+    ) -> Result<Vault, Box<dyn Error>> {
+        if amount > limits.available_debt {
+            return Err(Box::new(VaultError(format!(
+                "cannot draw {:.2} dai, the ilk is {:.2} dai from its debt ceiling",
+                amount, limits.available_debt
+            ))));
+        }
+        let mut vault = self.clone();
+        vault.debt = vault.debt.checked_add(amount)?;
+        vault.check_vat(true, price, limits)?;
+        Ok(vault)
+    }
+
+    pub fn payback(&self, amount: Wad, limits: &VatLimits) -> Result<Vault, Box<dyn Error>> {
+        if amount > self.debt {
+            return Err(Box::new(VaultError(format!(
+                "cannot pay back {:.2} dai, the debt is {:.2} dai",
+                amount, self.debt
+            ))));
+        }
+        let mut vault = self.clone();
+        vault.debt = vault.debt.checked_sub(amount)?;
+        vault.check_vat(false, Wad::zero(), limits)?;
+        Ok(vault)
+    }
+
+    /// The Vat checks on the changed vault: no dusty debt, and when the change made it
+    /// `riskier`, a ratio above the liquidation ratio at `price`.
+    fn check_vat(
+        &self,
+        riskier: bool,
+        price: Wad,
+        limits: &VatLimits,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.debt.is_zero() && self.debt < limits.dust {
+            return Err(Box::new(VaultError(format!(
+                "a debt of {:.2} dai is below the dust, {:.2} dai",
+                self.debt, limits.dust
+            ))));
+        }
+        if riskier && self.is_liquidatable(price, limits.liquidation_ratio)? {
+            return Err(Box::new(VaultError(format!(
+                "the ratio would be below {}% at {:.2}",
+                limits.liquidation_ratio.to_percent(),
+                price
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Prints the vault state at `price`, `btc_price` being the BTC value of one DAI and
+    /// `terms` the liquidation terms of the ilk.
+    pub fn show(
+        &self,
+        price: Wad,
+        btc_price: f64,
+        terms: &LiquidationTerms,
+    ) -> Result<(), Box<dyn Error>> {
+        let liquidation_ratio = terms.ratio;
+        let ratio = match self.get_ratio(price)? {
+            Some(ratio) => format!("{:.2}%", ratio.to_percent()),
+            None => String::from("no debt"),
+        };
+        println!("{:<11}: {:>9.2} ({})", "price", price, ratio);
+        for rule in &self.close_rules {
+            println!("{:<11}: {}", "close", rule);
+        }
+        if self.collateral.is_zero() {
+            println!("closed: {:.2} dai", self.dai);
+            return Ok(());
+        }
         println!(
             "{:<11}: {:>9.2} ({}% -> {}%)",
-            "down price", down_price, min_ratio_pc, repay_ratio_pc
+            "down price",
+            self.get_down_price()?,
+            self.min_ratio.to_percent(),
+            self.repay_ratio.to_percent()
         );
+        match self.is_boost_enabled() {
+            true => println!(
+                "{:<11}: {:>9.2} ({}% -> {}%)",
+                "up price",
+                self.get_up_price()?,
+                self.max_ratio.to_percent(),
+                self.boost_ratio.to_percent()
+            ),
+            false => println!("{:<11}: boost disabled", "up price"),
+        }
         println!(
-            "{:<11}: {:>9.2} ({}% -> {}%)",
-            "up price", up_price, max_ratio_pc, boost_ratio_pc
+            "{:<11}: {:>9.2} ({}%)",
+            "liquidation",
+            self.get_price_at_ratio(liquidation_ratio)?,
+            liquidation_ratio.to_percent()
         );
-        println!("{:<11}: {:>9.2} (150%)", "liquidation", liquidation_price);
+        if self.is_liquidatable(price, liquidation_ratio)? {
+            println!(
+                "liquidatable: the ratio is below {}%",
+                liquidation_ratio.to_percent()
+            );
+        }
+        if !self.debt.is_zero() {
+            let (_, liquidation) = self.liquidate(price, price, terms)?;
+            println!(
+                "loss if liquidated now: {:.2} dai ({:.4} {} sold at {:.2}, {:.4} returned)",
+                liquidation.penalty_loss,
+                liquidation.collateral_sold,
+                self.token.symbol.to_lowercase(),
+                liquidation.sale_price,
+                liquidation.collateral_returned
+            );
+        }
+        let dai_value = match self.get_equity(price)? {
+            Equity::Positive(value) => value.to_f64(),
+            Equity::Underwater(deficit) => {
+                println!("underwater by {:.2} dai", deficit);
+                return Ok(());
+            }
+        };
+        let eur_value = dai_value / 1.2271;
+        let col_value = self.get_col_value(price)?.to_f64();
+        let btc_value = dai_value * btc_price;
         println!("net value:");
         println!("{:>15.2} dai", dai_value);
         println!("{:>15.2} eur", eur_value);
         println!("{:>15.2} btc", btc_value);
-        println!("{:>15.2} eth", col_value);
+        println!("{:>15.2} {}", col_value, self.token.symbol.to_lowercase());
         Ok(())
     }
 }
@@ -170,7 +1096,7 @@ pub struct VaultError(pub String);
 
 impl fmt::Display for VaultError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{}", self.0)
     }
 }
 
@@ -179,3 +1105,218 @@ impl Error for VaultError {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipper::PriceCurve;
+    use crate::fixtures::{limits, vault, wad};
+    use crate::math::Ray;
+
+    fn rule(trigger: CloseTrigger) -> CloseRule {
+        CloseRule {
+            trigger,
+            to: CloseTo::Dai,
+        }
+    }
+
+    #[test]
+    fn stop_loss_and_take_profit_trigger_at_their_price() {
+        let stop_loss = rule(CloseTrigger::StopLoss(wad(80.0)));
+        assert_eq!(stop_loss.get_trigger_price(wad(81.0)).unwrap(), None);
+        assert_eq!(
+            stop_loss.get_trigger_price(wad(70.0)).unwrap(),
+            Some(wad(80.0))
+        );
+        let take_profit = rule(CloseTrigger::TakeProfit(wad(150.0)));
+        assert_eq!(take_profit.get_trigger_price(wad(149.0)).unwrap(), None);
+        assert_eq!(
+            take_profit.get_trigger_price(wad(150.0)).unwrap(),
+            Some(wad(150.0))
+        );
+    }
+
+    #[test]
+    fn trailing_stop_loss_follows_the_high() {
+        let mut rule = rule(CloseTrigger::TrailingStopLoss {
+            distance: wad(0.1),
+            high: wad(100.0),
+        });
+        assert_eq!(rule.get_trigger_price(wad(91.0)).unwrap(), None);
+        assert_eq!(rule.get_trigger_price(wad(90.0)).unwrap(), Some(wad(90.0)));
+        rule.observe(wad(200.0));
+        rule.observe(wad(150.0));
+        assert_eq!(rule.get_trigger_price(wad(181.0)).unwrap(), None);
+        assert_eq!(
+            rule.get_trigger_price(wad(170.0)).unwrap(),
+            Some(wad(180.0))
+        );
+    }
+
+    #[test]
+    fn trailing_take_profit_waits_for_the_activation() {
+        let mut rule = rule(CloseTrigger::TrailingTakeProfit {
+            activation: wad(150.0),
+            distance: wad(0.1),
+            high: wad(100.0),
+        });
+        // below the activation, a fall does not close
+        rule.observe(wad(140.0));
+        assert_eq!(rule.get_trigger_price(wad(50.0)).unwrap(), None);
+        rule.observe(wad(160.0));
+        assert_eq!(rule.get_trigger_price(wad(150.0)).unwrap(), None);
+        assert_eq!(
+            rule.get_trigger_price(wad(140.0)).unwrap(),
+            Some(wad(144.0))
+        );
+    }
+
+    fn terms(auction: Option<AuctionTerms>) -> LiquidationTerms {
+        LiquidationTerms {
+            ratio: Wad::from_percent(150),
+            penalty: Wad::from_percent(113),
+            auction,
+        }
+    }
+
+    #[test]
+    fn liquidation_without_auction_sells_at_the_market_price() {
+        // 180 collateral for 10000 dai of debt
+        let (vault, liquidation) = vault()
+            .liquidate(wad(80.0), wad(80.0), &terms(None))
+            .unwrap();
+        assert_eq!(liquidation.sale_price, wad(80.0));
+        assert_eq!(liquidation.debt, wad(10000.0));
+        // 11300 dai due at 80
+        assert_eq!(liquidation.collateral_sold, wad(141.25));
+        assert_eq!(liquidation.collateral_returned, wad(38.75));
+        assert_eq!(liquidation.penalty_loss, wad(1300.0));
+        assert_eq!(vault.collateral, wad(38.75));
+        assert!(vault.debt.is_zero());
+    }
+
+    #[test]
+    fn liquidation_auction_clears_below_the_market_price() {
+        let ray = |tenths: u64| Ray(Uint::exp10(26) * Uint::from(tenths));
+        let auction = AuctionTerms {
+            buf: ray(12),
+            tail: 10000,
+            cusp: ray(4),
+            curve: PriceCurve::Linear { tau: 1000 },
+        };
+        let (vault, liquidation) = vault()
+            .liquidate(wad(80.0), wad(80.0), &terms(Some(auction)))
+            .unwrap();
+        // from 96 down to the first second at or below 80, 96 * 833 / 1000
+        assert_eq!(liquidation.sale_price, Wad(wad(96.0).0 * 833 / 1000));
+        assert!(liquidation.collateral_sold > wad(141.25));
+        assert_eq!(liquidation.collateral_returned, vault.collateral);
+        // the discount costs on top of the penalty
+        assert!(liquidation.penalty_loss > wad(1300.0));
+        assert!(liquidation.penalty_loss < wad(1305.0));
+    }
+
+    #[test]
+    fn liquidation_of_an_underwater_vault_sells_everything() {
+        let (vault, liquidation) = vault()
+            .liquidate(wad(50.0), wad(50.0), &terms(None))
+            .unwrap();
+        // 226 collateral would be due, there are only 180
+        assert_eq!(liquidation.collateral_sold, wad(180.0));
+        assert!(liquidation.collateral_returned.is_zero());
+        // the 9000 dai raised do not even cover the debt
+        assert!(liquidation.penalty_loss.is_zero());
+        assert!(vault.collateral.is_zero());
+        assert!(vault.debt.is_zero());
+    }
+
+    #[test]
+    fn close_of_an_underwater_vault_is_a_total_loss() {
+        let vault = vault();
+        let friction = Friction::Constant(0.0);
+        let rule = rule(CloseTrigger::StopLoss(wad(50.0)));
+        // 180 collateral at 50 repays 9000 of the 10000 debt
+        let (closed, close) = vault.close(&rule, wad(50.0), wad(50.0), &friction).unwrap();
+        assert_eq!(close.shortfall, wad(1000.0));
+        assert_eq!(close.collateral_sold, vault.collateral);
+        assert!(closed.collateral.is_zero() && closed.debt.is_zero() && closed.dai.is_zero());
+        assert_eq!(
+            closed.get_equity(wad(50.0)).unwrap(),
+            Equity::Positive(Wad::zero())
+        );
+        let (closed, close) = vault
+            .close(&rule, wad(100.0), wad(100.0), &friction)
+            .unwrap();
+        assert!(close.shortfall.is_zero());
+        assert_eq!(closed.dai, wad(8000.0));
+    }
+
+    #[test]
+    fn withdraw_keeps_the_ratio_above_the_liquidation_ratio() {
+        // 180 collateral at 100 for 10000 debt, a 180% ratio
+        let vault = vault();
+        let price = wad(100.0);
+        let withdrawn = vault.withdraw(wad(20.0), price, &limits()).unwrap();
+        assert_eq!(withdrawn.collateral, wad(160.0));
+        assert!(vault.withdraw(wad(40.0), price, &limits()).is_err());
+        assert!(vault.withdraw(wad(200.0), price, &limits()).is_err());
+    }
+
+    #[test]
+    fn draw_keeps_the_ratio_and_the_debt_ceiling() {
+        let vault = vault();
+        let price = wad(100.0);
+        let drawn = vault.draw(wad(1000.0), price, &limits()).unwrap();
+        assert_eq!(drawn.debt, wad(11000.0));
+        assert!(vault.draw(wad(3000.0), price, &limits()).is_err());
+        let limits = VatLimits {
+            available_debt: wad(500.0),
+            ..limits()
+        };
+        assert!(vault.draw(wad(1000.0), price, &limits).is_err());
+    }
+
+    #[test]
+    fn payback_leaves_no_dusty_debt() {
+        let vault = vault();
+        assert_eq!(
+            vault.payback(wad(4000.0), &limits()).unwrap().debt,
+            wad(6000.0)
+        );
+        assert!(vault.payback(wad(6000.0), &limits()).is_err());
+        assert!(vault
+            .payback(wad(10000.0), &limits())
+            .unwrap()
+            .debt
+            .is_zero());
+        assert!(vault.payback(wad(11000.0), &limits()).is_err());
+        // paying back is allowed whatever the ratio
+        let mut unsafe_vault = vault.clone();
+        unsafe_vault.collateral = wad(100.0);
+        assert!(unsafe_vault.payback(wad(1000.0), &limits()).is_ok());
+    }
+
+    #[test]
+    fn delayed_trace_stops_when_underwater_at_the_market_price() {
+        let vault = vault();
+        let friction = Friction::Constant(0.01);
+        // 180 collateral for 10000 debt is underwater below 55.55
+        let trace = vault
+            .trace_vault_delayed(
+                Wad::from_f64(60.0).unwrap(),
+                Wad::from_f64(40.0).unwrap(),
+                &friction,
+            )
+            .unwrap();
+        assert!(trace.steps.is_empty());
+        assert_eq!(trace.vault.debt, vault.debt);
+        let trace = vault
+            .trace_vault_delayed(
+                Wad::from_f64(80.0).unwrap(),
+                Wad::from_f64(75.0).unwrap(),
+                &friction,
+            )
+            .unwrap();
+        assert_eq!(trace.steps[0].action, AutomationAction::Repay);
+    }
+}